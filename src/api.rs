@@ -1,24 +1,52 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use reqwest::header::HeaderMap;
 use reqwest_cookie_store::CookieStoreMutex;
 
-use crate::constants::DEFAULT_HEADERS;
+use crate::constants::{DEFAULT_HEADERS, DEFAULT_REQUEST_TIMEOUT, DEFAULT_USER_AGENT};
 
 pub(crate) mod auth;
 pub(crate) mod product;
 
+/// HTTP client settings that can be changed from the command line
+#[derive(Debug, Default)]
+pub(crate) struct ClientOpts {
+    /// Defaults to [`DEFAULT_REQUEST_TIMEOUT`]
+    pub(crate) timeout: Option<Duration>,
+    /// Defaults to [`DEFAULT_USER_AGENT`]
+    pub(crate) user_agent: Option<String>,
+    /// Sent with every request, on top of (or instead of) [`DEFAULT_HEADERS`]
+    pub(crate) headers: HeaderMap,
+    /// Don't use HTTP/2, even if the server supports it
+    pub(crate) http1_only: bool,
+}
+
 pub(crate) trait GalaClient {
-    fn with_gala(cookie_store: &Arc<CookieStoreMutex>) -> Self;
+    /// Builds a client for the IndieGala APIs. Requests that take longer than the timeout fail
+    /// instead of hanging.
+    ///
+    /// Connections are kept open and reused between requests, and HTTP/2 is used when the server
+    /// offers it while connecting, so chunks can share connections instead of each paying for a
+    /// new TLS handshake.
+    fn with_gala(cookie_store: &Arc<CookieStoreMutex>, opts: ClientOpts) -> Self;
 }
 
 impl GalaClient for reqwest::Client {
-    fn with_gala(cookie_store: &Arc<CookieStoreMutex>) -> Self {
-        reqwest::Client::builder()
-            .default_headers(DEFAULT_HEADERS.to_owned())
+    fn with_gala(cookie_store: &Arc<CookieStoreMutex>, opts: ClientOpts) -> Self {
+        let timeout = opts.timeout.unwrap_or(*DEFAULT_REQUEST_TIMEOUT);
+        let mut headers = DEFAULT_HEADERS.to_owned();
+        headers.extend(opts.headers);
+        let mut builder = reqwest::Client::builder()
+            .default_headers(headers)
             .cookie_provider(cookie_store.clone())
-            .user_agent("galaClient")
+            .user_agent(opts.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT))
             .use_rustls_tls()
-            .build()
-            .unwrap()
+            .timeout(timeout)
+            .connect_timeout(timeout);
+        if opts.http1_only {
+            builder = builder.http1_only();
+        }
+
+        builder.build().unwrap()
     }
 }
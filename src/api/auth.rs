@@ -1,3 +1,11 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{StatusCode, Url};
+use reqwest_cookie_store::CookieStoreMutex;
+
 use crate::{
     config::{LibraryConfig, UserConfig},
     constants::BASE_URL,
@@ -23,12 +31,113 @@ pub(crate) async fn login(
     }
 }
 
+/// How long imported cookies without an expiry date are kept, in seconds
+const IMPORTED_COOKIE_MAX_AGE: u64 = 60 * 60 * 24 * 30;
+
+/// Imports a session cookie copied from a browser, e.g. `name=value`, or several of them
+/// separated by `;` like in a Cookie header. Returns how many cookies were imported.
+pub(crate) fn import_session_token(cookie_store: &CookieStoreMutex, token: &str) -> usize {
+    let url = match Url::parse(&BASE_URL) {
+        Ok(url) => url,
+        Err(_) => return 0,
+    };
+    let domain = gala_domain(&url);
+    let mut cookie_store = cookie_store.lock().unwrap();
+
+    token
+        .split(';')
+        .map(str::trim)
+        .filter(|cookie| cookie.contains('='))
+        .filter(|cookie| {
+            cookie_store
+                .parse(
+                    &format!(
+                        "{cookie}; Domain={domain}; Path=/; Max-Age={IMPORTED_COOKIE_MAX_AGE}"
+                    ),
+                    &url,
+                )
+                .is_ok()
+        })
+        .count()
+}
+
+/// Imports the IndieGala cookies from a cookies.txt file in the Netscape format most browser
+/// extensions export. Cookies for other sites are ignored. Returns how many cookies were imported.
+pub(crate) fn import_cookie_file(
+    cookie_store: &CookieStoreMutex,
+    path: &Path,
+) -> std::io::Result<usize> {
+    let contents = std::fs::read_to_string(path)?;
+    let base_url = Url::parse(&BASE_URL).map_err(std::io::Error::other)?;
+    let gala_domain = gala_domain(&base_url);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut cookie_store = cookie_store.lock().unwrap();
+
+    let mut imported = 0;
+    for line in contents.lines() {
+        // HttpOnly cookies are prefixed like a comment
+        let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+        if line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [domain, _, path, secure, expires, name, value] = fields[..] else {
+            continue;
+        };
+        let host = domain.trim_start_matches('.');
+        if !host.ends_with(&gala_domain) {
+            continue;
+        }
+
+        // An expiry of 0 means it's a session cookie, which wouldn't be saved
+        let max_age = match expires.parse::<u64>() {
+            Ok(0) | Err(_) => IMPORTED_COOKIE_MAX_AGE,
+            Ok(expires) if expires > now => expires - now,
+            Ok(_) => continue,
+        };
+        let mut cookie = format!("{name}={value}; Domain={domain}; Path={path}; Max-Age={max_age}");
+        if secure == "TRUE" {
+            cookie.push_str("; Secure");
+        }
+        let url = match Url::parse(&format!("https://{host}{path}")) {
+            Ok(url) => url,
+            Err(_) => continue,
+        };
+        if cookie_store.parse(&cookie, &url).is_ok() {
+            imported += 1;
+        }
+    }
+
+    Ok(imported)
+}
+
+/// The domain IndieGala's cookies are set for, e.g. `indiegala.com` for `https://www.indiegala.com`
+fn gala_domain(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    host.strip_prefix("www.").unwrap_or(host).to_owned()
+}
+
+/// Fetches the user's info and library. Returns `None` if the session is invalid or has expired,
+/// and an error if the request itself failed, e.g. because of a network error.
 pub(crate) async fn sync(client: &reqwest::Client) -> Result<Option<SyncResult>, reqwest::Error> {
     let res = client
         .get(format!("{}/login_new/user_info", *BASE_URL))
         .send()
         .await?;
 
+    if matches!(
+        res.status(),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+    ) {
+        return Ok(None);
+    }
+    // Anything else that isn't a success is a problem on the server's end
+    let res = res.error_for_status()?;
+
     let body = res.text().await?;
 
     match serde_json::from_str::<UserInfo>(&body) {
@@ -56,9 +165,7 @@ pub(crate) async fn sync(client: &reqwest::Client) -> Result<Option<SyncResult>,
                 },
             }))
         }
-        Err(_) => {
-            println!("Failed to sync data. Are you logged in?");
-            Ok(None)
-        }
+        // Logged out users get a login page instead
+        Err(_) => Ok(None),
     }
 }
@@ -1,62 +1,313 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
 use bytes::Bytes;
+use futures::future::join_all;
+use indicatif::HumanDuration;
+use lazy_static::lazy_static;
+use log::{debug, trace, warn};
+use reqwest::{header, StatusCode};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    constants::{CONTENT_URL, DEV_URL},
+    constants::{
+        CONTENT_URL, DEV_URL, MANIFEST_ATTEMPTS, MANIFEST_RETRY_DELAY, MIRROR_PROBE_TIMEOUT,
+    },
+    error::CarnivalError,
+    helpers::read_manifest_validators,
     shared::models::api::{BuildOs, GameDetails, GameDetailsResponse, Product, ProductVersion},
 };
 
+/// The CDN mirror picked with `--mirror`, used instead of [`CONTENT_URL`]
+static MIRROR: OnceLock<String> = OnceLock::new();
+
+/// Probes every mirror with a HEAD request, and downloads from the one that answered fastest.
+/// The IndieGala CDN is used if none of them answer.
+pub(crate) async fn use_fastest_mirror(client: &reqwest::Client, mirrors: &[String]) {
+    let probes = mirrors.iter().map(|mirror| async move {
+        let started = Instant::now();
+        let res = client
+            .head(mirror)
+            .timeout(*MIRROR_PROBE_TIMEOUT)
+            .send()
+            .await;
+        // Any answer means the mirror is up, even an error status for its root
+        match res {
+            Ok(_) => {
+                let latency = started.elapsed();
+                debug!("{mirror} answered in {latency:?}");
+                Some((latency, mirror))
+            }
+            Err(err) => {
+                warn!("Mirror {mirror} didn't answer: {err}");
+                None
+            }
+        }
+    });
+
+    match join_all(probes).await.into_iter().flatten().min() {
+        Some((_, mirror)) => {
+            if !crate::output::is_quiet() {
+                println!("Downloading from {mirror}");
+            }
+            let _ = MIRROR.set(mirror.trim_end_matches('/').to_owned());
+        }
+        None => warn!(
+            "No mirror answered, downloading from {} instead",
+            *CONTENT_URL
+        ),
+    }
+}
+
+/// The hosts to download from, in the order they're tried: the mirror first if there's one, then
+/// the IndieGala CDN
+fn content_urls() -> impl Iterator<Item = &'static str> {
+    MIRROR
+        .get()
+        .map(|mirror| mirror.as_str())
+        .into_iter()
+        .chain(std::iter::once(CONTENT_URL.as_str()))
+}
+
 pub(crate) async fn get_build_manifest(
     client: &reqwest::Client,
     product: &Product,
     build_version: &ProductVersion,
-) -> Result<Bytes, reqwest::Error> {
-    let res = client
-        .get(format!(
-            "{}/DevShowCaseSourceVolume/dev_fold_{}/{}/{}/{}_manifest.csv",
-            *CONTENT_URL,
-            product.namespace,
-            product.id_key_name,
-            build_version.os,
-            build_version.version,
-        ))
-        .send()
-        .await?;
-    let body = res.bytes().await?;
-    Ok(body)
+) -> Result<Bytes, CarnivalError> {
+    get_manifest(client, product, build_version, "manifest").await
 }
 
 pub(crate) async fn get_build_manifest_chunks(
     client: &reqwest::Client,
     product: &Product,
     build_version: &ProductVersion,
-) -> Result<Bytes, reqwest::Error> {
-    let res = client
-        .get(format!(
-            "{}/DevShowCaseSourceVolume/dev_fold_{}/{}/{}/{}_manifest_chunks.csv",
-            *CONTENT_URL,
-            product.namespace,
-            product.id_key_name,
-            build_version.os,
-            build_version.version,
-        ))
-        .send()
-        .await?;
+) -> Result<Bytes, CarnivalError> {
+    get_manifest(client, product, build_version, "manifest_chunks").await
+}
+
+/// The `ETag` and `Last-Modified` headers a manifest was served with. They're stored next to the
+/// manifest, so it's only downloaded again if it changed on the CDN.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct ManifestValidators {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+}
+
+/// Build number, slug and suffix of a manifest
+type ManifestKey = (String, String, String);
+
+lazy_static! {
+    /// Validators of the manifests fetched during this run, until the manifest is stored with
+    /// `store_build_manifest`. Manifests that are never stored (e.g. with --check) don't get any.
+    static ref FETCHED_VALIDATORS: Mutex<HashMap<ManifestKey, ManifestValidators>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Takes the validators of a manifest fetched during this run, to store them with it
+pub(crate) fn take_manifest_validators(
+    build_number: &str,
+    product_slug: &str,
+    file_suffix: &str,
+) -> Option<ManifestValidators> {
+    FETCHED_VALIDATORS.lock().unwrap().remove(&(
+        build_number.to_owned(),
+        product_slug.to_owned(),
+        file_suffix.to_owned(),
+    ))
+}
+
+/// Fetches a manifest from the mirror, or from the IndieGala CDN if the mirror fails
+async fn get_manifest(
+    client: &reqwest::Client,
+    product: &Product,
+    build_version: &ProductVersion,
+    suffix: &str,
+) -> Result<Bytes, CarnivalError> {
+    // The stored manifest is reused if the CDN says it hasn't changed
+    let cached =
+        read_manifest_validators(&build_version.version, &product.slugged_name, suffix).await;
+    let mut content_urls = content_urls().peekable();
+    loop {
+        let content_url = content_urls.next().unwrap();
+        let result = get_manifest_from(
+            client,
+            content_url,
+            product,
+            build_version,
+            suffix,
+            cached.as_ref(),
+        )
+        .await;
+        match result {
+            Err(err) if content_urls.peek().is_some() => {
+                warn!("Failed to fetch {suffix} from {content_url}: {err}. Trying the IndieGala CDN...");
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Fetches a manifest from `content_url`, trying again with a growing delay if it fails in a way
+/// that could be temporary, like a dropped connection or a server error
+async fn get_manifest_from(
+    client: &reqwest::Client,
+    content_url: &str,
+    product: &Product,
+    build_version: &ProductVersion,
+    suffix: &str,
+    cached: Option<&(Vec<u8>, ManifestValidators)>,
+) -> Result<Bytes, CarnivalError> {
+    let mut delay = *MANIFEST_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match fetch_manifest(client, content_url, product, build_version, suffix, cached).await {
+            Err(err) if err.is_transient() && attempt < *MANIFEST_ATTEMPTS => {
+                warn!(
+                    "Failed to fetch {suffix}: {err}. Trying again in {}...",
+                    HumanDuration(delay)
+                );
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn fetch_manifest(
+    client: &reqwest::Client,
+    content_url: &str,
+    product: &Product,
+    build_version: &ProductVersion,
+    suffix: &str,
+    cached: Option<&(Vec<u8>, ManifestValidators)>,
+) -> Result<Bytes, CarnivalError> {
+    let url = format!(
+        "{}/DevShowCaseSourceVolume/dev_fold_{}/{}/{}/{}_{}.csv",
+        content_url,
+        product.namespace,
+        product.id_key_name,
+        build_version.os,
+        build_version.version,
+        suffix,
+    );
+    debug!("Fetching {suffix} from {url}");
+    let mut req = client.get(url);
+    if let Some((_, validators)) = cached {
+        if let Some(etag) = &validators.etag {
+            req = req.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            req = req.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let res = req.send().await?;
+    debug!("{suffix} request returned {}", res.status());
+    if let (StatusCode::NOT_MODIFIED, Some((manifest, validators))) = (res.status(), cached) {
+        debug!("Stored {suffix} is still up to date");
+        // Storing the manifest again would drop its validators otherwise
+        remember_validators(product, build_version, suffix, validators.clone());
+        return Ok(Bytes::from(manifest.clone()));
+    }
+    if !res.status().is_success() {
+        return Err(CarnivalError::ManifestNotFound {
+            version: build_version.version.to_owned(),
+            status: res.status(),
+        });
+    }
+
+    let is_html = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.contains("html"));
+    let header_value = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|value: &header::HeaderValue| value.to_str().ok())
+            .map(|value| value.to_owned())
+    };
+    let validators = ManifestValidators {
+        etag: header_value(header::ETAG),
+        last_modified: header_value(header::LAST_MODIFIED),
+    };
+    let content_length = res.content_length();
     let body = res.bytes().await?;
+    if let Some(expected) = content_length {
+        if (body.len() as u64) < expected {
+            return Err(CarnivalError::TruncatedManifest {
+                version: build_version.version.to_owned(),
+                expected,
+                actual: body.len() as u64,
+            });
+        }
+    }
+    // Error pages are sometimes served with a success status, so make sure this isn't one
+    let looks_like_markup = body
+        .iter()
+        .find(|b| !b.is_ascii_whitespace())
+        .is_none_or(|b| *b == b'<');
+    if is_html || looks_like_markup {
+        return Err(CarnivalError::InvalidManifest {
+            version: build_version.version.to_owned(),
+        });
+    }
+
+    if validators.etag.is_some() || validators.last_modified.is_some() {
+        remember_validators(product, build_version, suffix, validators);
+    }
+
     Ok(body)
 }
 
+/// Keeps the validators of a fetched manifest until it's stored
+fn remember_validators(
+    product: &Product,
+    build_version: &ProductVersion,
+    suffix: &str,
+    validators: ManifestValidators,
+) {
+    FETCHED_VALIDATORS.lock().unwrap().insert(
+        (
+            build_version.version.to_owned(),
+            product.slugged_name.to_owned(),
+            suffix.to_owned(),
+        ),
+        validators,
+    );
+}
+
 pub(crate) async fn download_chunk(
     client: &reqwest::Client,
     product: &Product,
     os: &BuildOs,
     chunk_sha: &String,
 ) -> Result<Bytes, reqwest::Error> {
-    let res = client
-        .get(get_chunk_url(product, os, chunk_sha))
-        .send()
-        .await?;
-    let bytes = res.bytes().await?;
-    Ok(bytes)
+    let mut content_urls = content_urls().peekable();
+    loop {
+        let url = get_chunk_url(content_urls.next().unwrap(), product, os, chunk_sha);
+        let is_mirror = content_urls.peek().is_some();
+        trace!("Fetching chunk from {url}");
+        // Mirrors that don't have the chunk are skipped. What the CDN sends is checked later.
+        let result = match client.get(&url).send().await {
+            Ok(res) if is_mirror => match res.error_for_status() {
+                Ok(res) => res.bytes().await,
+                Err(err) => Err(err),
+            },
+            Ok(res) => res.bytes().await,
+            Err(err) => Err(err),
+        };
+        match result {
+            Err(err) if is_mirror => {
+                debug!("Failed to fetch {url}: {err}. Trying the IndieGala CDN...");
+            }
+            result => return result,
+        }
+    }
 }
 
 pub(crate) async fn get_game_details(
@@ -77,7 +328,7 @@ pub(crate) async fn get_game_details(
     match serde_json::from_str::<GameDetailsResponse>(&body) {
         Ok(data) => {
             if data.status != "success" {
-                println!("Server failed to deliver game details");
+                println!("Server failed to deliver game details: {}", data.message);
                 return Ok(None);
             }
 
@@ -93,9 +344,9 @@ pub(crate) async fn get_game_details(
     }
 }
 
-fn get_chunk_url(product: &Product, os: &BuildOs, chunk_sha: &String) -> String {
+fn get_chunk_url(content_url: &str, product: &Product, os: &BuildOs, chunk_sha: &String) -> String {
     format!(
         "{}/DevShowCaseSourceVolume/dev_fold_{}/{}/{}/{}",
-        *CONTENT_URL, product.namespace, product.id_key_name, os, chunk_sha,
+        content_url, product.namespace, product.id_key_name, os, chunk_sha,
     )
 }
@@ -1,8 +1,17 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
 use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use glob::Pattern;
+use reqwest::header::{HeaderName, HeaderValue};
 
-use crate::{constants::*, shared::models::api::BuildOs};
+use crate::{
+    api::ClientOpts,
+    constants::*,
+    output::ColorChoice,
+    shared::models::api::{BuildOs, Product},
+};
 
 /// Native cross-platform indieGala client
 #[derive(Parser, Debug)]
@@ -15,21 +24,139 @@ use crate::{constants::*, shared::models::api::BuildOs};
 pub(crate) struct Cli {
     #[command(subcommand)]
     pub(crate) command: Commands,
+    /// When to use colored output
+    #[arg(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
+    /// Disable colored output. Same as `--color never`.
+    #[arg(long, global = true, conflicts_with = "color")]
+    no_color: bool,
+    /// Print what would be done without changing anything on disk or on your account. Works
+    /// with install, update, update-all, uninstall, move, adopt, import, launch, login and
+    /// logout.
+    #[arg(long, global = true)]
+    pub(crate) check: bool,
+    /// Don't show progress bars or any diagnostic output other than errors. Useful when running in
+    /// scripts or CI.
+    #[arg(long, short, global = true, visible_alias = "no-progress")]
+    pub(crate) quiet: bool,
+    /// Show diagnostic output. Pass it twice (-vv) for even more detail, like download stats while
+    /// installing. Must come before the command, since commands use -v for --version.
+    #[arg(long, short, action = clap::ArgAction::Count)]
+    pub(crate) verbose: u8,
+    /// Don't sync your library and only use what's saved locally. Commands that download games
+    /// don't work offline.
+    #[arg(long, global = true)]
+    pub(crate) offline: bool,
+    /// The User-Agent to send to the IndieGala servers. Defaults to the one the official client
+    /// uses.
+    #[arg(long, global = true, env = "CARNIVAL_USER_AGENT")]
+    user_agent: Option<String>,
+    /// An extra header to send with every request, e.g. "Accept-Language: en". Can be passed
+    /// more than once. Requests to --mirror hosts get it too, so don't put credentials in it when
+    /// using a mirror you don't trust.
+    #[arg(long = "header", value_name = "HEADER", global = true, value_parser = parse_header)]
+    headers: Vec<(HeaderName, HeaderValue)>,
 }
 
 impl Cli {
-    /// Checks if a sync is needed before handling command
-    pub(crate) fn needs_sync(&self) -> bool {
-        !matches!(
+    /// The color choice picked by the user, taking `--no-color` into account
+    pub(crate) fn color(&self) -> ColorChoice {
+        if self.no_color {
+            ColorChoice::Never
+        } else {
+            self.color
+        }
+    }
+
+    /// Checks if the command can't do its job without an internet connection
+    pub(crate) fn needs_network(&self) -> bool {
+        matches!(
             &self.command,
-            Commands::Login {
-                email: _,
-                password: _,
-            } | Commands::Logout
-                | Commands::Uninstall { slug: _, keep: _ }
-                | Commands::Verify { slug: _ }
+            Commands::Login { .. }
+                | Commands::Sync
+                | Commands::Install { .. }
+                | Commands::UpdateAll { .. }
+                | Commands::Update { .. }
         )
     }
+
+    /// Checks if the command reads or writes the stored build manifests
+    pub(crate) fn uses_manifests(&self) -> bool {
+        matches!(
+            &self.command,
+            Commands::Install { .. }
+                | Commands::Uninstall { .. }
+                | Commands::Adopt { .. }
+                | Commands::UpdateAll { .. }
+                | Commands::Update { .. }
+                | Commands::Info { .. }
+                | Commands::Diff { .. }
+                | Commands::Verify { .. }
+                | Commands::VerifyAll { .. }
+                | Commands::CleanManifests
+                | Commands::Import { .. }
+                | Commands::Clean { .. }
+        )
+    }
+
+    /// The HTTP client settings picked by the user. The request timeout and connection settings
+    /// can only be set for commands that download games.
+    pub(crate) fn client_opts(&self) -> ClientOpts {
+        let mut client_opts = ClientOpts {
+            user_agent: self.user_agent.clone(),
+            headers: self.headers.iter().cloned().collect(),
+            ..Default::default()
+        };
+        if let Commands::Install { install_opts, .. }
+        | Commands::UpdateAll { install_opts, .. }
+        | Commands::Update { install_opts, .. } = &self.command
+        {
+            client_opts.timeout = install_opts.request_timeout;
+            client_opts.http1_only = install_opts.http1;
+        }
+
+        client_opts
+    }
+
+    /// The CDN mirrors to pick from, for commands that download games
+    pub(crate) fn mirrors(&self) -> &[String] {
+        match &self.command {
+            Commands::Install { install_opts, .. }
+            | Commands::UpdateAll { install_opts, .. }
+            | Commands::Update { install_opts, .. } => &install_opts.mirrors,
+            _ => &[],
+        }
+    }
+
+    /// Checks if the command can still run with the saved library when syncing fails
+    pub(crate) fn works_offline(&self) -> bool {
+        matches!(&self.command, Commands::Launch { .. } | Commands::Status)
+    }
+
+    /// Checks if a sync is needed before handling command
+    pub(crate) fn needs_sync(&self) -> bool {
+        // Syncing stores the library, which --check promises not to do
+        !self.check
+            && !self.offline
+            && !matches!(
+                &self.command,
+                Commands::Login { .. }
+                    | Commands::Logout
+                    | Commands::Sync
+                    | Commands::Uninstall { .. }
+                    | Commands::Move { .. }
+                    | Commands::History { .. }
+                    | Commands::Verify { .. }
+                    | Commands::VerifyAll { .. }
+                    | Commands::CleanManifests
+                    | Commands::Clean { .. }
+                    | Commands::Export { .. }
+                    | Commands::Import { .. }
+                    | Commands::Diff { .. }
+                    | Commands::SetExe { .. }
+                    | Commands::Completions { .. }
+            )
+    }
 }
 
 #[derive(Debug, Subcommand)]
@@ -37,32 +164,95 @@ pub(crate) enum Commands {
     /// Authenticate with your indieGala account
     Login {
         /// Your indieGala account email
-        email: String,
+        #[arg(required_unless_present_any = ["token", "cookie_file"])]
+        email: Option<String>,
         /// Your indieGala password, can be left blank for interactive login
+        #[arg(requires = "email")]
         password: Option<String>,
+        /// Log in with a session cookie copied from your browser instead of your email and
+        /// password, e.g. if logging in needs 2FA or a captcha. Pass it as `name=value`, or
+        /// several separated by `;` like in a Cookie header.
+        #[arg(long, conflicts_with_all = ["email", "cookie_file"])]
+        token: Option<String>,
+        /// Log in by importing your browser's IndieGala cookies from a cookies.txt file (Netscape
+        /// format), instead of using your email and password
+        #[arg(long, conflicts_with_all = ["email", "token"])]
+        cookie_file: Option<PathBuf>,
     },
     /// Logout from your indieGala account
     Logout,
+    /// Fetch your library from indieGala again, e.g. to see games you just bought
+    ///
+    /// Commands that need the library already sync it first, unless --offline is passed.
+    Sync,
+    /// Show who you're logged in as, and a summary of your library and installed games
+    Status,
     /// List your library
-    Library,
-    /// Install a game from your library
+    Library {
+        /// Show the latest build of each game, its platform and whether it's installed
+        #[arg(long)]
+        detailed: bool,
+        /// Also show how much disk space the latest build of each game takes up. This fetches
+        /// the build manifest of every game that doesn't have it stored, so it can be slow.
+        #[arg(long, requires = "detailed")]
+        sizes: bool,
+    },
+    /// List installed games, their versions and whether they have an update
+    Installed,
+    /// Install one or more games from your library
     Install {
-        /// The slug of the game e.g. syberia-ii
-        slug: String,
+        /// The slugs of the games e.g. syberia-ii. Games are installed one after the other.
+        #[arg(required_unless_present = "from_file")]
+        slugs: Vec<String>,
+        /// Also install the games listed in this file, one slug per line. A line can pick a build
+        /// with `slug@version`. Blank lines and lines starting with `#` are skipped.
+        #[arg(long, conflicts_with_all = ["version", "path"])]
+        from_file: Option<PathBuf>,
         /// Install specific build version. If ommited, the latest build version will be installed.
+        ///
+        /// Can only be used when installing a single game.
         #[arg(long, short)]
         version: Option<String>,
         /// Base install path. The game will be installed in a subdirectory with the game's slugged
         /// name.
         #[arg(long)]
         base_path: Option<PathBuf>,
+        /// Where in the base install path to put each game, e.g. {namespace}/{slug} to group games
+        /// by publisher. Can use {slug}, {namespace}, {name} and {id}. Defaults to {slug}.
+        #[arg(long, value_parser = parse_install_prefix, conflicts_with_all = ["path", "reinstall"])]
+        install_prefix: Option<InstallPrefix>,
         /// Exact install path. The game will be installed in the selected directory without
         /// creating additional subdirectories.
+        ///
+        /// Can only be used when installing a single game.
         #[arg(long)]
         path: Option<PathBuf>,
-        /// The build target OS to install
+        /// The build target OS to install. A game can be installed once for each OS. Builds for
+        /// other OSes than this one can be launched with `launch --wrapper`.
         #[arg(long)]
         os: Option<BuildOs>,
+        /// The build target OSes to try in order, installing the first one the game has a build
+        /// for, e.g. linux,windows. Defaults to your OS first, with Windows as a fallback.
+        #[arg(long, value_delimiter = ',', conflicts_with = "os")]
+        platform_preference: Vec<BuildOs>,
+        /// Keep the downloaded files if an install fails, e.g. for debugging. By default, the
+        /// install directory is removed again, as long as it didn't exist before installing.
+        #[arg(long)]
+        keep_partial: bool,
+        /// Remove the existing install and install the game again from scratch, in the same
+        /// directory. Also overwrites install directories that exist but aren't tracked, e.g.
+        /// because an install failed, without asking.
+        #[arg(long, conflicts_with_all = ["path", "base_path"])]
+        reinstall: bool,
+        /// Only fetch and store the build manifests, without downloading the game, e.g. to
+        /// compare builds with `diff`.
+        #[arg(long, conflicts_with = "info")]
+        manifest_only: bool,
+        /// Also install the DLC of each game that's in your library, into the same directory as
+        /// the game. DLC is found by its slug, which starts with the game's, e.g.
+        /// syberia-ii-soundtrack for syberia-ii.
+        #[arg(long)]
+        with_dlc: bool,
         #[command(flatten)]
         install_opts: InstallOpts,
     },
@@ -70,12 +260,54 @@ pub(crate) enum Commands {
     Uninstall {
         /// The slug of the game e.g. syberia-ii
         slug: String,
-        /// Remove game from installed config but do not delete install folder.
+        /// Remove game from installed config but do not delete install folder. Its stored build
+        /// manifests are kept too, so it can be adopted or compared with `diff` later.
         #[arg(long)]
         keep: bool,
+        /// The OS of the install to uninstall. Only needed if the game is installed for more than one
+        /// OS.
+        #[arg(long)]
+        os: Option<BuildOs>,
+    },
+    /// Move an installed game to a different directory
+    Move {
+        /// The slug of the game e.g. syberia-ii
+        slug: String,
+        /// Exact path to move the game to. The game's files will be placed directly in this
+        /// directory, which must not exist yet.
+        new_path: PathBuf,
+        /// The OS of the install to move. Only needed if the game is installed for more than one
+        /// OS.
+        #[arg(long)]
+        os: Option<BuildOs>,
+    },
+    /// Start managing an existing install of a game that isn't tracked anymore
+    ///
+    /// Useful after losing your config, since the game doesn't have to be downloaded again.
+    Adopt {
+        /// The slug of the game e.g. syberia-ii
+        slug: String,
+        /// Exact path of the existing install
+        path: PathBuf,
+        /// The version that is installed. If not set, it's identified by checking the files
+        /// against the manifests of every available build.
+        #[arg(long, short)]
+        version: Option<String>,
     },
     /// Lists available updates for installed games.
-    ListUpdates,
+    ///
+    /// With --quiet, nothing is printed and the exit code tells if there are updates, e.g. for
+    /// notifications: 0 if there are none, 100 if there are and 1 if checking failed.
+    ListUpdates {
+        /// Only list updates built since this date (YYYY-MM-DD) or in this much time, e.g. 7d
+        #[arg(long, value_parser = parse_since)]
+        since: Option<NaiveDateTime>,
+    },
+    /// Update every installed game that has an available update.
+    UpdateAll {
+        #[command(flatten)]
+        install_opts: InstallOpts,
+    },
     /// Update (or downgrade) an installed game.
     Update {
         /// The slug of the game e.g. syberia-ii
@@ -86,42 +318,156 @@ pub(crate) enum Commands {
         /// You can get a list of available versions by using the `info` command.
         #[arg(long, short)]
         version: Option<String>,
+        /// Path to an existing install of this game that isn't tracked anymore (e.g. after losing
+        /// your config). The installed build is identified from the files on disk before
+        /// updating it.
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// The OS of the install to update. Only needed if the game is installed for more than one
+        /// OS.
+        #[arg(long)]
+        os: Option<BuildOs>,
+        /// Allow --version to be older than the installed build without asking first
+        #[arg(long)]
+        allow_downgrade: bool,
         #[command(flatten)]
         install_opts: InstallOpts,
     },
     /// Launch an installed game
     Launch {
+        /// The slug of the game e.g. syberia-ii. Part of the slug or name also works, as long as
+        /// it only matches one installed game.
+        slug: String,
+        /// The OS of the install to launch. Only needed if the game is installed for more than one
+        /// OS.
+        #[arg(long)]
+        os: Option<BuildOs>,
+        #[command(flatten)]
+        launch_opts: LaunchOpts,
+    },
+    /// Set the executable to launch for an installed game, instead of the one that's found
+    /// automatically
+    SetExe {
         /// The slug of the game e.g. syberia-ii
         slug: String,
-        /// Do not use wine
-        #[cfg(not(target_os = "windows"))]
+        /// The executable to launch, relative to the install path. Leave it out to go back to
+        /// finding the executable automatically.
+        path: Option<PathBuf>,
+        /// The OS of the install to change. Only needed if the game is installed for more than one
+        /// OS.
         #[arg(long)]
-        no_wine: bool,
-        /// The WINE prefix to use for this game
-        #[cfg(not(target_os = "windows"))]
+        os: Option<BuildOs>,
+    },
+    /// Print info about game
+    Info {
+        /// The slug of the game e.g. syberia-ii
+        slug: String,
+        /// Only print the build versions, one per line, e.g. for scripts. Pass --quiet too, so
+        /// nothing else is printed.
         #[arg(long)]
-        wine_prefix: Option<PathBuf>,
-        /// The WINE bin to use for launching the game
-        #[cfg(not(target_os = "windows"))]
+        versions: bool,
+        /// Only print the latest build version. Picks the builds that run best on your OS,
+        /// unless --os is set.
+        #[arg(long, conflicts_with = "versions")]
+        latest: bool,
+        /// List the files of the latest build and their sizes, without installing it. Picks the
+        /// builds that run best on your OS, unless --os is set.
+        #[arg(long, alias = "list-files", conflicts_with_all = ["versions", "latest"])]
+        files: bool,
+        /// Only show builds for this OS
         #[arg(long)]
-        wine: Option<PathBuf>,
-        /// Use a wrapper to launch
+        os: Option<BuildOs>,
+    },
+    /// Show the local history of installs, updates, uninstalls and launches
+    ///
+    /// The history is only stored on this machine.
+    History {
+        /// Only show the history of this game
         #[arg(long)]
-        wrapper: Option<PathBuf>,
+        slug: Option<String>,
     },
-    /// Print info about game
-    Info {
+    /// Show which files changed between two builds of a game
+    ///
+    /// Both builds' manifests must be stored already, e.g. with `install --manifest-only`.
+    Diff {
         /// The slug of the game e.g. syberia-ii
         slug: String,
+        /// The build version to compare from
+        old_version: String,
+        /// The build version to compare to
+        new_version: String,
+        /// Only print how many files changed, how much an update between the builds would
+        /// download, and how much more disk space it would need
+        #[arg(long)]
+        summary: bool,
     },
     /// Verify file integrity for an installed game
     Verify {
         /// The slug of the game e.g. syberia-ii
         slug: String,
+        /// The OS of the install to verify. Only needed if the game is installed for more than one
+        /// OS.
+        #[arg(long)]
+        os: Option<BuildOs>,
+        /// How many files to hash at one time. Defaults to your CPU count.
+        #[arg(long, default_value_t = *DEFAULT_VERIFY_WORKERS)]
+        workers: usize,
+        /// Hash every file, even ones that haven't changed since they last passed verification
+        #[arg(long)]
+        full: bool,
+    },
+    /// Verify file integrity for every installed game
+    ///
+    /// Keeps going when a game fails, and exits with an error if any of them did, e.g. to find
+    /// the games to reinstall after a disk issue.
+    VerifyAll {
+        /// How many files to hash at one time. Defaults to your CPU count.
+        #[arg(long, default_value_t = *DEFAULT_VERIFY_WORKERS)]
+        workers: usize,
+        /// Hash every file, even ones that haven't changed since they last passed verification
+        #[arg(long)]
+        full: bool,
+    },
+    /// Remove stored build manifests that aren't needed by installed games anymore
+    ///
+    /// The manifests of installed builds are kept, since they're needed to update and verify
+    /// them.
+    CleanManifests,
+    /// Forget installs whose directory is gone, and list directories that aren't tracked installs
+    ///
+    /// Untracked directories are only listed, never removed.
+    Clean {
+        /// Actually forget the installs whose directory is gone. Without it, they're only listed.
+        #[arg(long)]
+        yes: bool,
+        /// The base install path to look for untracked directories in. Defaults to the default
+        /// base install path.
+        #[arg(long)]
+        base_path: Option<PathBuf>,
+    },
+    /// Save the records of your installed games to a file, e.g. to move them to another machine
+    Export {
+        /// The file to save the records to
+        path: PathBuf,
+    },
+    /// Add the installed games from a file made by export
+    ///
+    /// Games whose install directory doesn't exist on this machine are skipped, and games that
+    /// are already installed keep their current record.
+    Import {
+        /// The file made by export
+        path: PathBuf,
+    },
+    /// Print a completion script for your shell
+    ///
+    /// For example, add `source <(freecarnival completions bash)` to your ~/.bashrc.
+    Completions {
+        /// The shell to generate completions for
+        shell: Shell,
     },
 }
 
-#[derive(Debug, Args)]
+#[derive(Debug, Clone, Args)]
 pub(crate) struct InstallOpts {
     /// How many download workers to run at one time.
     /// Increasing this value will make downloads faster, but use more memory.
@@ -131,18 +477,298 @@ pub(crate) struct InstallOpts {
     /// double your CPU_COUNT. You shouldn't deviate too much from this.
     #[arg(long, default_value_t = *DEFAULT_MAX_DL_WORKERS)]
     pub(crate) max_download_workers: usize,
-    /// How much memory to use to store chunks. Lowering this value will potentially make
-    /// downloads slower while being lighter on memory usage. Raising it will make the program
-    /// use more memory if needed, but can potentially speed up downloads.
+    /// How much memory to use to store chunks, including the ones waiting to be written to
+    /// disk. Lowering this value will potentially make downloads slower while being lighter on
+    /// memory usage. Raising it will make the program use more memory if needed, but can
+    /// potentially speed up downloads.
     #[arg(long, default_value_t = *DEFAULT_MAX_MEMORY_USAGE)]
     pub(crate) max_memory_usage: usize,
+    /// How many chunks to write to disk at one time. Chunks are written straight to where they
+    /// belong in their file, so any worker can write any of them. Raising this can speed up
+    /// installs on fast SSDs, where writing to disk with a single worker can't keep up with the
+    /// downloads.
+    #[arg(long, default_value_t = 1)]
+    pub(crate) write_workers: usize,
     /// Print download info instead of installing game.
     #[arg(long, short)]
     pub(crate) info: bool,
+    /// Don't ask before starting downloads of 1 GiB or more. Nothing is asked either when stdin
+    /// isn't a terminal.
+    #[arg(long, short)]
+    pub(crate) yes: bool,
     /// Skip verifying chunks. This will make downloads faster but won't check for
     /// corrupted/tampered files.
     #[arg(long)]
     pub(crate) skip_verify: bool,
+    /// Check every file against the build manifest once the install is done, like the verify
+    /// command does. Catches files that got corrupted on disk after their chunks were checked.
+    #[arg(long, conflicts_with = "skip_verify")]
+    pub(crate) verify: bool,
+    /// Reuse build manifests fetched less than this long ago, and fetch older ones again, e.g.
+    /// 30m, 12h or 7d. Generated delta manifests older than this are regenerated.
+    ///
+    /// By default, build manifests are always fetched again, and delta manifests are always
+    /// reused.
+    #[arg(long, value_parser = parse_duration)]
+    pub(crate) max_manifest_age: Option<Duration>,
+    /// How long to wait before doubling the number of download workers when a download starts,
+    /// e.g. 250ms or 1s. Downloads start with a single worker and ramp up to
+    /// --max-download-workers, instead of hitting the CDN with every worker at once, which can
+    /// make downloads unreliable. Set it to 0 to start every worker right away.
+    #[arg(long, value_parser = parse_duration, default_value = "250ms")]
+    pub(crate) download_ramp_up: Duration,
+    /// Find the number of download workers that works best for your connection, instead of
+    /// always ramping up to --max-download-workers. Downloads start with a single worker, which
+    /// is doubled every 2 seconds for as long as downloads keep getting faster. Raise
+    /// --max-download-workers too on high-latency connections, since it's still the limit.
+    #[arg(long, conflicts_with = "download_ramp_up")]
+    pub(crate) auto_tune: bool,
+    /// Give up on a request to the IndieGala servers if it takes longer than this, e.g. 30s or 2m,
+    /// instead of letting a stuck download hang forever. Defaults to 30s.
+    #[arg(long, value_parser = parse_duration)]
+    pub(crate) request_timeout: Option<Duration>,
+    /// Don't download files that match this glob pattern, or are in a directory that does, e.g.
+    /// optional language packs like "Content/Localization". Can be passed more than once.
+    /// Patterns are remembered, so updates and verify skip the same files.
+    #[arg(long, value_parser = parse_glob)]
+    pub(crate) exclude: Vec<Pattern>,
+    /// Download over HTTP/1.1 only. By default, HTTP/2 is used if the CDN supports it, which
+    /// lets chunks share connections. Try this if downloads are unreliable.
+    #[arg(long)]
+    pub(crate) http1: bool,
+    /// Download chunks and manifests from this mirror of the IndieGala CDN, e.g. one that's
+    /// closer to you. When more than one is given, the one that answers fastest is used. Files
+    /// the mirror fails to serve are downloaded from the IndieGala CDN. Requests to the mirror
+    /// carry the same --header values as the ones to IndieGala.
+    #[arg(
+        long = "mirror",
+        value_name = "URL",
+        env = "CARNIVAL_MIRRORS",
+        value_delimiter = ','
+    )]
+    pub(crate) mirrors: Vec<String>,
+    /// Set from the global `--check` flag
+    #[arg(skip)]
+    pub(crate) check: bool,
+    /// Set from `install --manifest-only`
+    #[arg(skip)]
+    pub(crate) manifest_only: bool,
+}
+
+/// Parses headers like `Name: value`
+fn parse_header(value: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, header_value) = value.split_once(':').ok_or(format!(
+        "{value} is not a valid header. Use \"Name: value\"."
+    ))?;
+    let name = HeaderName::try_from(name.trim()).map_err(|err| format!("{name}: {err}"))?;
+    let header_value =
+        HeaderValue::try_from(header_value.trim()).map_err(|err| format!("{name}: {err}"))?;
+
+    Ok((name, header_value))
+}
+
+/// A path template for `install --install-prefix`, checked to only use known fields
+#[derive(Debug, Clone)]
+pub(crate) struct InstallPrefix(String);
+
+impl InstallPrefix {
+    const FIELDS: [&'static str; 4] = ["slug", "namespace", "name", "id"];
+
+    /// Fills in the template with `product`'s fields. Slashes and characters that aren't allowed
+    /// in file names are replaced in the values, so each one stays a single directory name. Empty
+    /// values and `.`/`..` become `_`, so they can't change which directory that is. Fails if the
+    /// result still isn't a relative path inside the base install path.
+    pub(crate) fn expand(&self, product: &Product) -> Result<PathBuf, String> {
+        let mut expanded = self.0.clone();
+        for field in Self::FIELDS {
+            let value = match field {
+                "slug" => product.slugged_name.clone(),
+                "namespace" => product.namespace.clone(),
+                "name" => product.name.clone(),
+                _ => product.id.to_string(),
+            };
+            let value = value.replace(['/', '\\', '<', '>', ':', '"', '|', '?', '*'], "_");
+            let value = match value.trim() {
+                "" | "." | ".." => "_",
+                value => value,
+            };
+            expanded = expanded.replace(&format!("{{{field}}}"), value);
+        }
+
+        if expanded.trim().is_empty() || leaves_base_path(&expanded) {
+            return Err(format!(
+                "--install-prefix turns into {expanded} for {}, which isn't inside the base install path",
+                product.slugged_name
+            ));
+        }
+
+        Ok(PathBuf::from(expanded))
+    }
+}
+
+/// Whether `path` could point outside the directory it's joined to, on any OS: an absolute path,
+/// a drive letter, or a `..`. Install prefixes can end up in configs that are shared between OSes.
+fn leaves_base_path(path: &str) -> bool {
+    let mut chars = path.chars();
+    let drive_letter =
+        chars.next().is_some_and(|c| c.is_ascii_alphabetic()) && chars.next() == Some(':');
+    drive_letter
+        || path.starts_with(['/', '\\'])
+        || std::path::Path::new(path).has_root()
+        || path.split(['/', '\\']).any(|component| component == "..")
+}
+
+fn parse_install_prefix(value: &str) -> Result<InstallPrefix, String> {
+    if leaves_base_path(value) {
+        return Err(format!("{value} has to stay inside the base install path."));
+    }
+
+    let mut rest = value;
+    while let Some(start) = rest.find(['{', '}']) {
+        if rest[start..].starts_with('}') {
+            return Err(format!("{value} has a }} without a matching {{."));
+        }
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("{value} has a {{ without a matching }}.")),
+        };
+        let field = &rest[start + 1..end];
+        if !InstallPrefix::FIELDS.contains(&field) {
+            return Err(format!(
+                "{{{field}}} isn't a known field. Use {}.",
+                InstallPrefix::FIELDS
+                    .map(|field| format!("{{{field}}}"))
+                    .join(", ")
+            ));
+        }
+        rest = &rest[end + 1..];
+    }
+
+    Ok(InstallPrefix(value.to_owned()))
+}
+
+fn parse_glob(value: &str) -> Result<Pattern, String> {
+    Pattern::new(value).map_err(|err| format!("{value} is not a valid pattern: {err}"))
+}
+
+/// Parses environment variables like `KEY=VALUE`. The value can be empty.
+fn parse_env_var(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some(("", _)) => Err(format!("{value} is missing a variable name.")),
+        Some((key, _)) if key.contains(char::is_whitespace) || key.contains('\0') => {
+            Err(format!("{key} is not a valid variable name."))
+        }
+        Some((_, env_value)) if env_value.contains('\0') => Err(format!(
+            "The value of {value} can't contain NUL characters."
+        )),
+        Some((key, env_value)) => Ok((key.to_owned(), env_value.to_owned())),
+        None => Err(format!("{value} is not a valid variable. Use KEY=VALUE.")),
+    }
+}
+
+/// Parses a date like `2024-05-01`, or how long ago like `7d` (see [`parse_duration`])
+fn parse_since(value: &str) -> Result<NaiveDateTime, String> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(NaiveTime::MIN));
+    }
+
+    let duration = parse_duration(value)
+        .map_err(|_| format!("{value} is not a date like 2024-05-01 or a duration like 7d."))?;
+    chrono::Duration::from_std(duration)
+        .ok()
+        .and_then(|duration| Local::now().naive_local().checked_sub_signed(duration))
+        .ok_or(format!("{value} is too long ago."))
+}
+
+/// Parses durations like `90`, `500ms`, `90s`, `30m`, `12h` or `7d`. Plain numbers are seconds.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let (number, unit_millis) = match value.char_indices().last() {
+        Some((i, 's')) if value[..i].ends_with('m') => (&value[..i - 1], 1),
+        Some((i, 's')) => (&value[..i], 1000),
+        Some((i, 'm')) => (&value[..i], 1000 * 60),
+        Some((i, 'h')) => (&value[..i], 1000 * 60 * 60),
+        Some((i, 'd')) => (&value[..i], 1000 * 60 * 60 * 24),
+        _ => (value, 1000),
+    };
+
+    let number = number.parse::<u64>().map_err(|_| {
+        format!("{value} is not a valid duration. Use a number followed by ms, s, m, h or d.")
+    })?;
+    number
+        .checked_mul(unit_millis)
+        .map(Duration::from_millis)
+        .ok_or(format!("{value} is too long a duration"))
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct LaunchOpts {
+    /// Do not use wine
+    #[cfg(not(target_os = "windows"))]
+    #[arg(long)]
+    pub(crate) no_wine: bool,
+    /// The WINE prefix to use for this game. It will be remembered for future launches.
+    #[cfg(not(target_os = "windows"))]
+    #[arg(long)]
+    pub(crate) wine_prefix: Option<PathBuf>,
+    /// The WINE bin to use for launching the game. It will be remembered for future launches.
+    #[cfg(not(target_os = "windows"))]
+    #[arg(long)]
+    pub(crate) wine: Option<PathBuf>,
+    /// Use a wrapper to launch. Builds for other OSes (e.g. a Windows build in CrossOver) are
+    /// launched through it too, without needing --wine.
+    #[arg(long)]
+    pub(crate) wrapper: Option<PathBuf>,
+    /// Launch through Feral GameMode's gamemoderun. Works together with --mangohud and
+    /// --wrapper.
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub(crate) gamemode: bool,
+    /// Launch through MangoHud to show its overlay. Works together with --gamemode and
+    /// --wrapper.
+    #[cfg(target_os = "linux")]
+    #[arg(long)]
+    pub(crate) mangohud: bool,
+    /// An environment variable to set for the game, e.g. MANGOHUD=1. Can be passed more than
+    /// once.
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+    pub(crate) envs: Vec<(String, String)>,
+    /// A command to run before launching the game, e.g. to set up a RAM disk. The game isn't
+    /// launched if it fails.
+    #[arg(long)]
+    pub(crate) pre_launch: Option<String>,
+    /// A command to run after the game exits, e.g. to clean up after --pre-launch
+    #[arg(long)]
+    pub(crate) post_launch: Option<String>,
+    /// Start the game in the background and return right away, e.g. when launching from a menu
+    /// or hotkey. The game keeps running when the terminal is closed. Its PID is saved to
+    /// <slug>.pid in the runtime directory. Play time isn't tracked, since the game's exit isn't
+    /// waited for.
+    #[arg(long, conflicts_with = "post_launch")]
+    pub(crate) detach: bool,
+    /// Launch the game as administrator right away. Games that fail to start because they need
+    /// administrator rights are launched as administrator anyway, this just skips the first try.
+    /// Can't be used with --detach, which would save the PID of the UAC prompt's helper instead
+    /// of the game's.
+    #[cfg(target_os = "windows")]
+    #[arg(long, conflicts_with = "detach")]
+    pub(crate) admin: bool,
+    /// The executable to launch, relative to the install path. It will be remembered for future
+    /// launches.
+    ///
+    /// If this isn't set and there are several executables to choose from, you will be asked
+    /// which one to launch.
+    #[arg(long)]
+    pub(crate) exe: Option<PathBuf>,
+    /// Fetch the game's launch details from indieGala again instead of using the ones saved
+    /// when it was installed
+    #[arg(long)]
+    pub(crate) refresh: bool,
+    /// Set from the global `--check` flag
+    #[arg(skip)]
+    pub(crate) check: bool,
+    /// Set from the global `--offline` flag
+    #[arg(skip)]
+    pub(crate) offline: bool,
 }
 
 impl ValueEnum for BuildOs {
@@ -173,3 +799,94 @@ impl ValueEnum for BuildOs {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn product(name: &str) -> Product {
+        Product {
+            namespace: "ns".to_owned(),
+            slugged_name: "game".to_owned(),
+            id: 1,
+            name: name.to_owned(),
+            id_key_name: "key".to_owned(),
+            version: vec![],
+        }
+    }
+
+    #[test]
+    fn install_prefixes_have_to_stay_inside_the_base_path() {
+        for value in [
+            "..",
+            "games/../..",
+            r"games\..\..",
+            "/games",
+            r"\games",
+            "C:/games",
+            r"c:\games",
+            "C:games",
+        ] {
+            assert!(parse_install_prefix(value).is_err(), "{value}");
+        }
+        assert!(parse_install_prefix("games/{slug}").is_ok());
+    }
+
+    #[test]
+    fn product_names_stay_a_single_directory() {
+        let prefix = parse_install_prefix("{name}/{slug}").unwrap();
+        let expand = |name| prefix.expand(&product(name)).unwrap();
+        assert_eq!(expand("AC/DC"), PathBuf::from("AC_DC/game"));
+        assert_eq!(expand("../.."), PathBuf::from(".._../game"));
+        assert_eq!(expand(".."), PathBuf::from("_/game"));
+        assert_eq!(expand("   "), PathBuf::from("_/game"));
+        assert_eq!(expand("C:"), PathBuf::from("C_/game"));
+    }
+
+    #[test]
+    fn install_prefixes_only_use_known_fields() {
+        assert!(parse_install_prefix("{namespace}/{name} ({id})/{slug}").is_ok());
+        assert!(parse_install_prefix("{version}").is_err());
+        assert!(parse_install_prefix("{}").is_err());
+        assert!(parse_install_prefix("{slug").is_err());
+        assert!(parse_install_prefix("slug}").is_err());
+    }
+
+    #[test]
+    fn install_prefixes_that_expand_to_nothing_are_an_error() {
+        for value in ["", "  "] {
+            let prefix = parse_install_prefix(value).unwrap();
+            assert!(prefix.expand(&product("Game")).is_err(), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn durations_take_a_unit_and_default_to_seconds() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(
+            parse_duration("7d"),
+            Ok(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+    }
+
+    #[test]
+    fn durations_need_a_number() {
+        for value in ["", "s", "ms", "m", "1.5h", "-1", "10 m"] {
+            assert!(parse_duration(value).is_err(), "{value:?}");
+        }
+    }
+
+    #[test]
+    fn durations_too_long_to_count_in_milliseconds_are_an_error() {
+        assert!(parse_duration(&u64::MAX.to_string()).is_err());
+        assert!(parse_duration(&format!("{}s", u64::MAX)).is_err());
+        assert_eq!(
+            parse_duration(&format!("{}ms", u64::MAX)),
+            Ok(Duration::from_millis(u64::MAX))
+        );
+    }
+}
@@ -1,15 +1,15 @@
 use std::collections::HashMap;
 
+use chrono::Local;
 use confy::ConfyError;
 use reqwest_cookie_store::CookieStore;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
 use crate::{
-    constants::CONFIG_PATH,
-    constants::PROJECT_NAME,
+    constants::{CONFIG_PATH, PROJECT_DIRS},
     shared::models::{
-        api::{Product, UserInfo},
+        api::{BuildOs, Product, UserInfo},
         InstallInfo,
     },
 };
@@ -22,28 +22,91 @@ where
         confy::load_path::<Self>(Self::get_config_path())
     }
 
+    /// Like [`GalaConfig::load`], but falls back to the default config if the file is corrupted.
+    /// It's moved aside first, so what's left of it isn't overwritten the next time the config is
+    /// stored. Other errors (e.g. no permission to read it) exit, since starting over would lose
+    /// a config that's still fine.
+    fn load_or_default() -> Self {
+        let err = match Self::load() {
+            Ok(config) => return config,
+            Err(err) => err,
+        };
+
+        let reason = error_reason(&err);
+        let path = Self::get_config_path();
+        if !matches!(err, ConfyError::BadYamlData(_)) || !path.is_file() {
+            println!("Failed to load {}: {}", path.display(), reason);
+            std::process::exit(1);
+        }
+
+        let backup_path = backup_path(&path);
+        match std::fs::rename(&path, &backup_path) {
+            Ok(()) => println!(
+                "Couldn't load {} ({}), starting over. It was moved to {}.",
+                path.display(),
+                reason,
+                backup_path.display()
+            ),
+            Err(err) => println!(
+                "Couldn't load {} ({}), starting over. Failed to move it out of the way: {}",
+                path.display(),
+                reason,
+                err
+            ),
+        }
+
+        Self::default()
+    }
+
     fn store(&self) -> Result<(), ConfyError> {
         confy::store_path(Self::get_config_path(), self)
     }
 
+    /// Loads the config from a file that isn't the usual one, e.g. one made by `export`. The file
+    /// has to exist, or an empty one is made.
+    fn load_from(path: &Path) -> Result<Self, ConfyError> {
+        confy::load_path::<Self>(path)
+    }
+
+    /// Stores the config somewhere other than the usual file, e.g. for `export`
+    fn store_to(&self, path: &Path) -> Result<(), ConfyError> {
+        confy::store_path(path, self)
+    }
+
     fn clear() -> Result<(), ConfyError> {
-        confy::store_path(Self::get_config_path(), Self::default())
+        Self::default().store()
     }
 
     fn config_name() -> &'static str;
 
     fn get_config_path() -> PathBuf {
-        if !CONFIG_PATH.is_empty() {
+        let config_dir = if !CONFIG_PATH.is_empty() {
             Path::new(&(*CONFIG_PATH))
-                .join(format!("{}.yml", Self::config_name()))
-                .to_path_buf()
         } else {
-            match confy::get_configuration_file_path(*PROJECT_NAME, Self::config_name()) {
-                Ok(p) => PathBuf::from(p.to_str().unwrap_or_default()).to_owned(),
-                Err(_e) => panic!("Can't get config path for {}", Self::config_name()),
-            }
-        }
+            PROJECT_DIRS.config_dir()
+        };
+
+        config_dir.join(format!("{}.yml", Self::config_name()))
+    }
+}
+
+/// What went wrong loading or storing a config. confy's own messages leave it out.
+pub(crate) fn error_reason(err: &ConfyError) -> String {
+    std::error::Error::source(err).map_or(err.to_string(), ToString::to_string)
+}
+
+/// A path to move a corrupted config to that no earlier backup uses, e.g.
+/// installed.yml.20240101-120000.bak
+fn backup_path(path: &Path) -> PathBuf {
+    let timestamp = Local::now().format("%Y%m%d-%H%M%S");
+    let mut backup_path = path.with_extension(format!("yml.{timestamp}.bak"));
+    let mut n = 1;
+    while backup_path.exists() {
+        backup_path = path.with_extension(format!("yml.{timestamp}-{n}.bak"));
+        n += 1;
     }
+
+    backup_path
 }
 
 #[derive(Default, Debug, Serialize, Deserialize)]
@@ -61,6 +124,18 @@ impl GalaConfig for UserConfig {
 pub(crate) struct CookieConfig(pub(crate) CookieStore);
 
 impl GalaConfig for CookieConfig {
+    /// The session cookies are as good as a password, so only the user can read them
+    #[cfg(unix)]
+    fn store(&self) -> Result<(), ConfyError> {
+        use std::os::unix::fs::PermissionsExt;
+
+        confy::store_path_perms(
+            Self::get_config_path(),
+            self,
+            std::fs::Permissions::from_mode(0o600),
+        )
+    }
+
     fn config_name() -> &'static str {
         "cookies"
     }
@@ -77,10 +152,217 @@ impl GalaConfig for LibraryConfig {
     }
 }
 
-pub(crate) type InstalledConfig = HashMap<String, InstallInfo>;
+/// Every install of a game. There's usually only one, but a game can be installed once per OS.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "StoredInstalledGame", into = "StoredInstalledGame")]
+pub(crate) struct InstalledGame(Vec<InstallInfo>);
+
+/// Games installed for a single OS are stored the same way they were before multiple installs were
+/// supported, so existing configs keep working.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredInstalledGame {
+    Single(Box<InstallInfo>),
+    Multiple(Vec<InstallInfo>),
+}
+
+impl From<StoredInstalledGame> for InstalledGame {
+    fn from(stored: StoredInstalledGame) -> Self {
+        match stored {
+            StoredInstalledGame::Single(install_info) => Self(vec![*install_info]),
+            StoredInstalledGame::Multiple(installs) => Self(installs),
+        }
+    }
+}
+
+impl From<InstalledGame> for StoredInstalledGame {
+    fn from(mut game: InstalledGame) -> Self {
+        match game.0.len() {
+            1 => Self::Single(Box::new(game.0.remove(0))),
+            _ => Self::Multiple(game.0),
+        }
+    }
+}
+
+impl InstalledGame {
+    /// Finds the install for `os`. If `os` isn't set, the game must only be installed once.
+    fn position(&self, slug: &str, os: Option<&BuildOs>) -> Result<usize, String> {
+        match os {
+            Some(os) => self
+                .0
+                .iter()
+                .position(|install_info| &install_info.os == os)
+                .ok_or(format!("{slug} is not installed for {os}.")),
+            None => match self.0.len() {
+                0 => Err(format!("{slug} is not installed.")),
+                1 => Ok(0),
+                _ => Err(format!(
+                    "{slug} is installed for {}. Use --os to pick one.",
+                    self.0
+                        .iter()
+                        .map(|install_info| install_info.os.to_string())
+                        .collect::<Vec<String>>()
+                        .join(" and ")
+                )),
+            },
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &InstallInfo> {
+        self.0.iter()
+    }
+}
+
+pub(crate) type InstalledConfig = HashMap<String, InstalledGame>;
+
+/// Lookups for [`InstalledConfig`] that take into account a game can be installed for several
+/// OSes
+pub(crate) trait InstalledGames {
+    /// Finds the install of `slug` for `os`. If `os` isn't set, the game must only be installed
+    /// once. The error explains why no install was found.
+    fn get_install(&self, slug: &str, os: Option<&BuildOs>) -> Result<&InstallInfo, String>;
+
+    fn get_install_mut(
+        &mut self,
+        slug: &str,
+        os: Option<&BuildOs>,
+    ) -> Result<&mut InstallInfo, String>;
+
+    fn remove_install(&mut self, slug: &str, os: Option<&BuildOs>) -> Result<InstallInfo, String>;
+
+    /// Adds `install_info`, replacing any previous install of `slug` for the same OS
+    fn insert_install(&mut self, slug: String, install_info: InstallInfo);
+
+    /// Every install, sorted by slug and OS
+    fn installs(&self) -> Vec<(&String, &InstallInfo)>;
+}
+
+impl InstalledGames for InstalledConfig {
+    fn get_install(&self, slug: &str, os: Option<&BuildOs>) -> Result<&InstallInfo, String> {
+        let game = self.get(slug).ok_or(format!("{slug} is not installed."))?;
+        let index = game.position(slug, os)?;
+
+        Ok(&game.0[index])
+    }
+
+    fn get_install_mut(
+        &mut self,
+        slug: &str,
+        os: Option<&BuildOs>,
+    ) -> Result<&mut InstallInfo, String> {
+        let game = self
+            .get_mut(slug)
+            .ok_or(format!("{slug} is not installed."))?;
+        let index = game.position(slug, os)?;
+
+        Ok(&mut game.0[index])
+    }
+
+    fn remove_install(&mut self, slug: &str, os: Option<&BuildOs>) -> Result<InstallInfo, String> {
+        let game = self
+            .get_mut(slug)
+            .ok_or(format!("{slug} is not installed."))?;
+        let index = game.position(slug, os)?;
+        let install_info = game.0.remove(index);
+        if game.0.is_empty() {
+            self.remove(slug);
+        }
+
+        Ok(install_info)
+    }
+
+    fn insert_install(&mut self, slug: String, install_info: InstallInfo) {
+        let game = self.entry(slug).or_default();
+        game.0.retain(|installed| installed.os != install_info.os);
+        game.0.push(install_info);
+    }
+
+    fn installs(&self) -> Vec<(&String, &InstallInfo)> {
+        let mut installs: Vec<(&String, &InstallInfo)> = self
+            .iter()
+            .flat_map(|(slug, game)| game.iter().map(move |install_info| (slug, install_info)))
+            .collect();
+        installs.sort_by_key(|(slug, install_info)| (*slug, install_info.os.to_string()));
+
+        installs
+    }
+}
 
 impl GalaConfig for InstalledConfig {
     fn config_name() -> &'static str {
         "installed"
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constants::PROJECT_NAME;
+
+    /// A config file of its own for each test, in the temp directory
+    fn config_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "{}-{}-{name}.yml",
+            *PROJECT_NAME,
+            std::process::id()
+        ))
+    }
+
+    fn round_trip(name: &str, installed: &InstalledConfig) -> (String, InstalledConfig) {
+        let path = config_path(name);
+        installed.store_to(&path).unwrap();
+        let stored = std::fs::read_to_string(&path).unwrap();
+        let loaded = InstalledConfig::load_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (stored, loaded)
+    }
+
+    #[test]
+    fn installs_from_before_multiple_installs_still_load() {
+        let path = config_path("single-install");
+        std::fs::write(
+            &path,
+            "game:\n  install_path: /games/game\n  version: '1'\n  os: lin\n",
+        )
+        .unwrap();
+        let installed = InstalledConfig::load_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            installed.get_install("game", None).unwrap(),
+            &InstallInfo::new("/games/game".into(), "1".to_owned(), BuildOs::Linux)
+        );
+    }
+
+    #[test]
+    fn a_single_install_is_stored_like_before() {
+        let mut installed = InstalledConfig::new();
+        installed.insert_install(
+            "game".to_owned(),
+            InstallInfo::new("/games/game".into(), "1".to_owned(), BuildOs::Linux),
+        );
+
+        let (stored, loaded) = round_trip("one-install", &installed);
+        assert!(stored.contains("game:\n  install_path:"), "{stored}");
+        assert_eq!(loaded, installed);
+    }
+
+    #[test]
+    fn installs_for_several_oses_are_stored_as_a_list() {
+        let mut installed = InstalledConfig::new();
+        for (path, os) in [
+            ("/games/win", BuildOs::Windows),
+            ("/games/lin", BuildOs::Linux),
+        ] {
+            installed.insert_install(
+                "game".to_owned(),
+                InstallInfo::new(path.into(), "1".to_owned(), os),
+            );
+        }
+
+        let (stored, loaded) = round_trip("two-installs", &installed);
+        assert!(stored.contains("game:\n- install_path:"), "{stored}");
+        assert_eq!(loaded, installed);
+        assert_eq!(loaded.installs().len(), 2);
+    }
+}
@@ -1,23 +1,50 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
-use directories::UserDirs;
+use directories::{ProjectDirs, UserDirs};
 use lazy_static::lazy_static;
 use reqwest::header::{self, HeaderMap};
 
+pub(crate) const DEFAULT_USER_AGENT: &str = "galaClient";
+
 lazy_static! {
-    pub(crate) static ref BASE_URL: &'static str = "https://www.indiegala.com";
-    pub(crate) static ref CONTENT_URL: &'static str = "https://content.indiegalacdn.com";
-    pub(crate) static ref DEV_URL: &'static str = "https://developers.indiegala.com";
+    pub(crate) static ref BASE_URL: String = url_from_env("CARNIVAL_BASE_URL", "https://www.indiegala.com");
+    pub(crate) static ref CONTENT_URL: String = url_from_env("CARNIVAL_CONTENT_URL", "https://content.indiegalacdn.com");
+    pub(crate) static ref DEV_URL: String = url_from_env("CARNIVAL_DEV_URL", "https://developers.indiegala.com");
+    /// The size of the chunks builds are usually split into. Builds that use another size are
+    /// handled too.
     pub(crate) static ref MAX_CHUNK_SIZE: usize = 1048576; // 1 MiB
     pub(crate) static ref DEFAULT_MAX_DL_WORKERS: usize = std::cmp::min(num_cpus::get() * 2, 16);
+    /// How long download speed is measured for before `--auto-tune` adds download workers
+    pub(crate) static ref AUTO_TUNE_WINDOW: Duration = Duration::from_secs(2);
+    /// `--auto-tune` keeps adding download workers while they make downloads at least this much
+    /// faster
+    pub(crate) static ref AUTO_TUNE_MIN_SPEEDUP: f64 = 1.1;
+    pub(crate) static ref DEFAULT_VERIFY_WORKERS: usize = num_cpus::get();
+    /// How many progress events can wait to be drawn before installs and verifies wait for them
+    pub(crate) static ref PROGRESS_EVENTS_BUFFER: usize = 1024;
     pub(crate) static ref DEFAULT_MAX_MEMORY_USAGE: usize = *MAX_CHUNK_SIZE * 1024; // 1 GiB
+    /// How many times fetching a build manifest is tried before giving up
+    pub(crate) static ref MANIFEST_ATTEMPTS: u32 = 4;
+    /// How long to wait before trying to fetch a build manifest again. It doubles after each try.
+    pub(crate) static ref MANIFEST_RETRY_DELAY: Duration = Duration::from_secs(1);
+    pub(crate) static ref DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+    /// Mirrors that take longer than this to answer aren't used, see `--mirror`
+    pub(crate) static ref MIRROR_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Downloads at least this big ask for confirmation first, see `--yes`
+    pub(crate) static ref LARGE_DOWNLOAD_SIZE: f64 = 1073741824f64; // 1 GiB
+    pub(crate) static ref MIN_RELIABLE_PLAY_TIME: Duration = Duration::from_secs(30);
+    pub(crate) static ref MAX_HISTORY_SIZE: u64 = 1048576; // 1 MiB
+    pub(crate) static ref MAX_FAILED_CHUNKS_LOG_SIZE: u64 = 1048576; // 1 MiB
     pub(crate) static ref DEFAULT_BASE_INSTALL_PATH: PathBuf = UserDirs::new().expect("Failed to retrieve home directory.").home_dir().join("Games").join(*PROJECT_NAME);
     pub(crate) static ref PROJECT_NAME: &'static str = env!("CARGO_PKG_NAME");
     pub(crate) static ref PROJECT_VERSION: &'static str = env!("CARGO_PKG_VERSION");
     pub(crate) static ref VERSION_CODENAME: &'static str = include_str!("../CODENAME");
+    /// Where config files, manifests and the history are stored. Follows the XDG base directories
+    /// on Linux.
+    pub(crate) static ref PROJECT_DIRS: ProjectDirs = ProjectDirs::from("rs", "", *PROJECT_NAME).expect("Failed to retrieve home directory.");
     pub(crate) static ref CONFIG_PATH: String = {
         match std::env::var("CARNIVAL_CONFIG_PATH") {
-            Ok(p) => String::from(p),
+            Ok(p) => p,
             Err(_e) => "".to_string()
         }
     };
@@ -33,3 +60,12 @@ lazy_static! {
         default_headers
     };
 }
+
+/// Reads a base URL override from `var`, e.g. to test against a local mirror. Falls back to
+/// `default` if it's unset or empty.
+fn url_from_env(var: &str, default: &str) -> String {
+    match std::env::var(var) {
+        Ok(url) if !url.is_empty() => url.trim_end_matches('/').to_owned(),
+        _ => default.to_owned(),
+    }
+}
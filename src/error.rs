@@ -0,0 +1,67 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+
+/// Everything that can go wrong while installing, updating or verifying games
+#[derive(Debug, Error)]
+pub(crate) enum CarnivalError {
+    #[error("Network request failed: {0}")]
+    Network(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse manifest: {0}")]
+    Csv(#[from] csv::Error),
+    /// The server didn't return the manifest, e.g. because the build doesn't exist or you're not
+    /// logged in anymore
+    #[error("Build manifest not found for version {version} ({status}). Are you logged in?")]
+    ManifestNotFound { version: String, status: StatusCode },
+    /// The server returned something that isn't a manifest, like an HTML error page
+    #[error(
+        "The server didn't return a valid build manifest for version {version}. Are you logged in?"
+    )]
+    InvalidManifest { version: String },
+    /// The connection dropped before the whole manifest was received
+    #[error(
+        "Only received {actual} of {expected} bytes of the build manifest for version {version}"
+    )]
+    TruncatedManifest {
+        version: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("{chunk} failed verification. {file_path} is corrupted.")]
+    Verification { chunk: String, file_path: String },
+    /// A chunk that can't be the right one, e.g. a truncated download or an error page
+    #[error("{chunk} of {file_path} is {actual} bytes, but should be {expected} bytes")]
+    ChunkSize {
+        chunk: String,
+        file_path: String,
+        expected: u64,
+        actual: u64,
+    },
+    /// A file didn't end up the size the manifest says it should be, e.g. because the disk filled
+    /// up while writing it
+    #[error("{file_path} is {actual} bytes, but should be {expected} bytes. Is the disk full?")]
+    IncompleteFile {
+        file_path: String,
+        expected: u64,
+        actual: u64,
+    },
+    #[error("A background task failed: {0}")]
+    Task(#[from] tokio::task::JoinError),
+    /// The user didn't confirm a large download. Not a failure, the game is only skipped.
+    #[error("Cancelled")]
+    Cancelled,
+}
+
+impl CarnivalError {
+    /// Whether trying again later could work, e.g. after a network error or a server error
+    pub(crate) fn is_transient(&self) -> bool {
+        match self {
+            CarnivalError::Network(_) | CarnivalError::TruncatedManifest { .. } => true,
+            CarnivalError::ManifestNotFound { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            _ => false,
+        }
+    }
+}
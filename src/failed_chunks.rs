@@ -0,0 +1,40 @@
+//! A local log of chunks that failed verification, so the details can be attached to bug reports
+//! after the terminal has scrolled past them.
+
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::{
+    constants::{MAX_FAILED_CHUNKS_LOG_SIZE, PROJECT_DIRS},
+    jsonl,
+};
+
+#[derive(Debug, Serialize)]
+pub(crate) struct FailedChunk<'a> {
+    pub(crate) timestamp: DateTime<Local>,
+    pub(crate) slug: &'a str,
+    pub(crate) os: String,
+    /// File the chunk belongs to, as it's named in the build manifest
+    pub(crate) file_path: &'a str,
+    /// The chunk's SHA as it's listed in the chunks manifest
+    pub(crate) chunk: &'a str,
+    pub(crate) expected_sha: &'a str,
+    pub(crate) computed_sha: String,
+    pub(crate) size: usize,
+}
+
+pub(crate) fn failed_chunks_path() -> PathBuf {
+    PROJECT_DIRS.cache_dir().join("failed_chunks.jsonl")
+}
+
+/// Appends a failed chunk to the log. It's recorded while downloads are running, so telling the
+/// user about it is up to the caller.
+pub(crate) fn record(failed_chunk: &FailedChunk) -> std::io::Result<()> {
+    jsonl::append(
+        &failed_chunks_path(),
+        *MAX_FAILED_CHUNKS_LOG_SIZE,
+        failed_chunk,
+    )
+}
@@ -1,34 +1,52 @@
 use std::{
-    collections::{HashMap, HashSet},
-    path::PathBuf,
-    sync::Arc,
+    collections::{hash_map::Entry, HashMap, HashSet},
+    ffi::OsString,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use async_recursion::async_recursion;
 use bytes::Bytes;
-use directories::ProjectDirs;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use chrono::Local;
+use glob::Pattern;
+use human_bytes::human_bytes;
+use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
+use log::{debug, log_enabled, trace, warn, Level};
 use os_path::OsPath;
 use queues::{queue, IsQueue, Queue};
 use sha2::{Digest, Sha256};
 use tokio::{
     fs::File,
-    io::AsyncWriteExt,
-    sync::{OwnedSemaphorePermit, Semaphore},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    sync::{OnceCell, OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
 };
 
 use crate::{
-    api,
+    api::{self, product::ManifestValidators},
     cli::InstallOpts,
-    constants::{MAX_CHUNK_SIZE, PROJECT_NAME},
+    config::{InstalledConfig, InstalledGames},
+    constants::{
+        AUTO_TUNE_MIN_SPEEDUP, AUTO_TUNE_WINDOW, MAX_CHUNK_SIZE, PROJECT_DIRS, PROJECT_NAME,
+    },
+    error::CarnivalError,
+    failed_chunks::{self, failed_chunks_path, FailedChunk},
+    output::{is_quiet, progress_bars, PauseListener},
+    progress::{report, ProgressEvent, ProgressSender},
     shared::models::{
         api::{BuildOs, Product},
         BuildManifestChunksRecord, BuildManifestRecord, ChangeTag,
     },
 };
 
+/// Collects every exe under `path` that could be the game's executable. Exes closer to `path` come
+/// first, and installers/uninstallers are left out.
 #[async_recursion]
-pub(crate) async fn find_exe_recursive(path: &PathBuf) -> Option<PathBuf> {
+pub(crate) async fn find_exes_recursive(path: &PathBuf) -> Vec<PathBuf> {
     let mut subdirs = vec![];
     let mut exes = vec![];
 
@@ -43,7 +61,7 @@ pub(crate) async fn find_exe_recursive(path: &PathBuf) -> Option<PathBuf> {
 
                 if entry_path.is_file() {
                     // Check if the current path is a file with a .exe extension
-                    println!("Checking file: {}", entry_path.display());
+                    trace!("Checking file: {}", entry_path.display());
                     if let (Some(ext), Some(file_name)) =
                         (entry_path.extension(), entry_path.file_name())
                     {
@@ -66,37 +84,100 @@ pub(crate) async fn find_exe_recursive(path: &PathBuf) -> Option<PathBuf> {
         }
     }
 
-    if !exes.is_empty() {
-        exes.sort();
-        return Some(exes.swap_remove(0));
+    exes.sort();
+    subdirs.sort();
+    for dir in subdirs {
+        trace!("Checking directory: {}", dir.display());
+        exes.append(&mut find_exes_recursive(&dir).await);
     }
 
-    for dir in subdirs {
-        println!("Checking directory: {}", dir.display());
-        if let Some(exe_path) = find_exe_recursive(&dir.to_path_buf()).await {
-            return Some(exe_path);
+    exes
+}
+
+/// Returns the free space in bytes on the filesystem `path` is (or would be) on
+pub(crate) fn available_space(path: &Path) -> std::io::Result<u64> {
+    // The path itself might not exist yet, so check the closest ancestor that does
+    let existing_path = path
+        .ancestors()
+        .find(|p| p.exists())
+        .unwrap_or(Path::new("."));
+    fs2::available_space(existing_path)
+}
+
+/// Returns the total size in bytes of all files under `path`
+#[async_recursion]
+pub(crate) async fn dir_size(path: &Path) -> tokio::io::Result<u64> {
+    let mut size = 0;
+    let mut entries = tokio::fs::read_dir(path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let metadata = entry.metadata().await?;
+        if metadata.is_dir() {
+            size += dir_size(&entry.path()).await?;
+        } else {
+            size += metadata.len();
         }
     }
 
-    None
+    Ok(size)
+}
+
+/// Recursively copies the directory `from` to `to`, creating `to` if needed
+#[async_recursion]
+pub(crate) async fn copy_dir_recursive(from: &Path, to: &Path) -> tokio::io::Result<()> {
+    tokio::fs::create_dir_all(to).await?;
+    let mut entries = tokio::fs::read_dir(from).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let target = to.join(entry.file_name());
+        if entry.file_type().await?.is_dir() {
+            copy_dir_recursive(&entry.path(), &target).await?;
+        } else {
+            tokio::fs::copy(entry.path(), &target).await?;
+        }
+    }
+
+    Ok(())
 }
 
 pub(crate) async fn read_or_generate_delta_manifest(
-    slug: &String,
+    slug: &str,
     old_manifest_bytes: &[u8],
     new_manifest_bytes: &[u8],
     old_version: &String,
     new_version: &String,
+    max_age: Option<Duration>,
 ) -> tokio::io::Result<Vec<u8>> {
     let manifest_delta_version = format!("{}_{}", old_version, new_version);
-    if let Ok(exising_delta) =
-        read_build_manifest(&manifest_delta_version, slug, "manifest_delta").await
-    {
-        println!("Using existing delta manifest");
+    let existing_delta = match max_age {
+        Some(max_age) => {
+            read_fresh_build_manifest(&manifest_delta_version, slug, "manifest_delta", max_age)
+                .await
+        }
+        None => read_build_manifest(&manifest_delta_version, slug, "manifest_delta").await,
+    };
+    if let Ok(exising_delta) = existing_delta {
+        debug!("Using existing delta manifest");
         return Ok(exising_delta);
     }
 
-    println!("Generating delta manifest...");
+    debug!("Generating delta manifest...");
+    let delta_bytes = generate_delta_manifest(old_manifest_bytes, new_manifest_bytes);
+    store_build_manifest(
+        &delta_bytes,
+        &format!("{}_{}", old_version, new_version),
+        slug,
+        "manifest_delta",
+    )
+    .await?;
+
+    Ok(delta_bytes)
+}
+
+/// Compares two build manifests and returns a manifest of the files that were added, modified or
+/// removed, tagged with a [`ChangeTag`]
+pub(crate) fn generate_delta_manifest(
+    old_manifest_bytes: &[u8],
+    new_manifest_bytes: &[u8],
+) -> Vec<u8> {
     let mut new_manifest_rdr = csv::Reader::from_reader(new_manifest_bytes);
     let new_manifest_iter: Vec<BuildManifestRecord> = new_manifest_rdr
         .byte_records()
@@ -169,34 +250,36 @@ pub(crate) async fn read_or_generate_delta_manifest(
                 .expect("Failed to serialize delta build manifest");
         }
     }
-    let delta_bytes = build_manifest_delta_wtr.into_inner().unwrap();
-    store_build_manifest(
-        &delta_bytes,
-        &format!("{}_{}", old_version, new_version),
-        slug,
-        "manifest_delta",
-    )
-    .await?;
-
-    Ok(delta_bytes)
+    build_manifest_delta_wtr.into_inner().unwrap()
 }
 
 pub(crate) async fn read_or_generate_delta_chunks_manifest(
-    slug: &String,
+    slug: &str,
     delta_manifest_bytes: &[u8],
     new_manifest_bytes: &[u8],
     old_version: &String,
     new_version: &String,
+    max_age: Option<Duration>,
 ) -> tokio::io::Result<Vec<u8>> {
     let manifest_delta_version = format!("{}_{}", old_version, new_version);
-    if let Ok(exising_delta) =
-        read_build_manifest(&manifest_delta_version, slug, "manifest_delta_chunks").await
-    {
-        println!("Using existing chunks delta manifest");
+    let existing_delta = match max_age {
+        Some(max_age) => {
+            read_fresh_build_manifest(
+                &manifest_delta_version,
+                slug,
+                "manifest_delta_chunks",
+                max_age,
+            )
+            .await
+        }
+        None => read_build_manifest(&manifest_delta_version, slug, "manifest_delta_chunks").await,
+    };
+    if let Ok(exising_delta) = existing_delta {
+        debug!("Using existing chunks delta manifest");
         return Ok(exising_delta);
     }
 
-    println!("Generating chunks delta manifest...");
+    debug!("Generating chunks delta manifest...");
     let mut delta_manifest_rdr = csv::Reader::from_reader(delta_manifest_bytes);
     let mut delta_manifest = delta_manifest_rdr.byte_records().map(|r| {
         let record = r.expect("Failed to get byte record");
@@ -222,15 +305,15 @@ pub(crate) async fn read_or_generate_delta_chunks_manifest(
             break;
         }
 
-        // We want to ignore chunks for removed files and folders
-        while current_file.is_directory() || current_file.is_empty() {
+        // We want to ignore chunks for removed files and folders, and files without chunks
+        while current_file.is_directory() || current_file.is_empty() || current_file.chunks == 0 {
             current_file = match delta_manifest.next() {
                 Some(file) => {
-                    println!("Skipping over {}", current_file.file_name);
+                    trace!("Skipping over {}", current_file.file_name);
                     file.expect("Failed to deserialize build manifest delta")
                 }
                 None => {
-                    println!("Done processing delta chunks");
+                    debug!("Done processing delta chunks");
                     break;
                 }
             };
@@ -245,12 +328,12 @@ pub(crate) async fn read_or_generate_delta_chunks_manifest(
             .expect("Failed to serialize build manifest chunks");
 
         if usize::from(record.id) + 1 == current_file.chunks {
-            println!("Done processing chunks for {}", record.file_path);
+            trace!("Done processing chunks for {}", record.file_path);
             // Move on to the next file
             current_file = match delta_manifest.next() {
                 Some(file) => file.expect("Failed to deserialize build manifest delta"),
                 None => {
-                    println!("Done processing delta chunks");
+                    debug!("Done processing delta chunks");
                     break;
                 }
             };
@@ -271,86 +354,505 @@ pub(crate) async fn read_or_generate_delta_chunks_manifest(
 
 pub(crate) async fn store_build_manifest(
     body: &[u8],
-    build_number: &String,
-    product_slug: &String,
+    build_number: &str,
+    product_slug: &str,
     file_suffix: &str,
 ) -> tokio::io::Result<()> {
-    let project = ProjectDirs::from("rs", "", *PROJECT_NAME).unwrap();
-    let path = project.config_dir().join("manifests").join(product_slug);
-    tokio::fs::create_dir_all(&path).await?;
+    let path = manifest_path(build_number, product_slug, file_suffix);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
 
-    let path = path.join(format!("{}_{}.csv", build_number, file_suffix));
-    tokio::fs::write(path, body).await
+    tokio::fs::write(path, body).await?;
+    // Validators of a manifest that was replaced don't match this one anymore
+    let validators_path = manifest_validators_path(build_number, product_slug, file_suffix);
+    match api::product::take_manifest_validators(build_number, product_slug, file_suffix) {
+        Some(validators) => {
+            tokio::fs::write(validators_path, serde_json::to_vec(&validators)?).await
+        }
+        None if validators_path.exists() => tokio::fs::remove_file(validators_path).await,
+        None => Ok(()),
+    }
+}
+
+/// Reads a stored manifest together with the validators it was served with, if it has any
+pub(crate) async fn read_manifest_validators(
+    build_number: &str,
+    product_slug: &str,
+    file_suffix: &str,
+) -> Option<(Vec<u8>, ManifestValidators)> {
+    let validators = tokio::fs::read(manifest_validators_path(
+        build_number,
+        product_slug,
+        file_suffix,
+    ))
+    .await
+    .ok()?;
+    let validators = serde_json::from_slice(&validators).ok()?;
+    let manifest = read_build_manifest(build_number, product_slug, file_suffix)
+        .await
+        .ok()?;
+
+    Some((manifest, validators))
 }
 
 pub(crate) async fn read_build_manifest(
-    build_number: &String,
-    product_slug: &String,
+    build_number: &str,
+    product_slug: &str,
     file_suffix: &str,
 ) -> tokio::io::Result<Vec<u8>> {
-    let project = ProjectDirs::from("rs", "", *PROJECT_NAME).unwrap();
-    let path = project
-        .config_dir()
-        .join("manifests")
+    tokio::fs::read(manifest_path(build_number, product_slug, file_suffix)).await
+}
+
+/// Manifests are downloaded data that can be fetched again, so they're kept out of the config
+/// directory
+fn manifests_dir() -> PathBuf {
+    PROJECT_DIRS.data_dir().join("manifests")
+}
+
+fn manifest_validators_path(build_number: &str, product_slug: &str, file_suffix: &str) -> PathBuf {
+    manifests_dir()
+        .join(product_slug)
+        .join(format!("{}_{}.json", build_number, file_suffix))
+}
+
+fn manifest_path(build_number: &str, product_slug: &str, file_suffix: &str) -> PathBuf {
+    manifests_dir()
         .join(product_slug)
-        .join(format!("{}_{}.csv", build_number, file_suffix));
+        .join(format!("{}_{}.csv", build_number, file_suffix))
+}
+
+/// Moves manifests stored by older versions in the config directory to [`manifests_dir`], so
+/// installed games can still be updated and verified. Manifests already in [`manifests_dir`] are
+/// kept, so this also picks up what an older version wrote after a downgrade.
+pub(crate) async fn migrate_manifests() -> tokio::io::Result<()> {
+    let old_manifests_dir = PROJECT_DIRS.config_dir().join("manifests");
+    let manifests_dir = manifests_dir();
+    // On macOS, config and data share a directory
+    if old_manifests_dir == manifests_dir || !tokio::fs::try_exists(&old_manifests_dir).await? {
+        return Ok(());
+    }
+
+    debug!(
+        "Moving manifests from {} to {}",
+        old_manifests_dir.display(),
+        manifests_dir.display()
+    );
+    move_missing_files(&old_manifests_dir, &manifests_dir).await?;
+    tokio::fs::remove_dir_all(&old_manifests_dir).await
+}
+
+/// Moves every file in `from` that doesn't exist in `to` yet, keeping the directory structure
+#[async_recursion]
+async fn move_missing_files(from: &Path, to: &Path) -> tokio::io::Result<()> {
+    tokio::fs::create_dir_all(to).await?;
+    let mut entries = tokio::fs::read_dir(from).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let target = to.join(entry.file_name());
+        if entry.file_type().await?.is_dir() {
+            move_missing_files(&entry.path(), &target).await?;
+        } else if !tokio::fs::try_exists(&target).await? {
+            // The directories can be on different filesystems, which rename can't handle
+            if tokio::fs::rename(entry.path(), &target).await.is_err() {
+                tokio::fs::copy(entry.path(), &target).await?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes every stored manifest that isn't needed anymore. Only the build manifest and chunks of
+/// builds that are still installed are kept, since `update` and `verify` rely on them. If `slug` is
+/// set, only its manifests are pruned.
+///
+/// Returns how many files were removed (or would be, with `check`) and their total size in bytes.
+pub(crate) async fn prune_build_manifests(
+    installed: &InstalledConfig,
+    slug: Option<&str>,
+    check: bool,
+) -> tokio::io::Result<(usize, u64)> {
+    let manifests_path = manifests_dir();
+    if !manifests_path.exists() {
+        return Ok((0, 0));
+    }
+
+    let mut keep = HashSet::new();
+    for (installed_slug, install_info) in installed.installs() {
+        for suffix in ["manifest", "manifest_chunks"] {
+            keep.insert(manifest_path(&install_info.version, installed_slug, suffix));
+            keep.insert(manifest_validators_path(
+                &install_info.version,
+                installed_slug,
+                suffix,
+            ));
+        }
+    }
+
+    let mut slug_paths = vec![];
+    match slug {
+        Some(slug) => slug_paths.push(manifests_path.join(slug)),
+        None => {
+            let mut entries = tokio::fs::read_dir(&manifests_path).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                slug_paths.push(entry.path());
+            }
+        }
+    }
+
+    let mut removed = 0;
+    let mut removed_bytes = 0;
+    for slug_path in slug_paths {
+        if !slug_path.is_dir() {
+            continue;
+        }
+
+        let mut kept_any = false;
+        let mut entries = tokio::fs::read_dir(&slug_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if keep.contains(&path) {
+                kept_any = true;
+                continue;
+            }
+
+            debug!("Removing {}", path.display());
+            removed += 1;
+            removed_bytes += entry.metadata().await?.len();
+            if !check {
+                tokio::fs::remove_file(&path).await?;
+            }
+        }
+
+        if !kept_any && !check {
+            tokio::fs::remove_dir(&slug_path).await?;
+        }
+    }
+
+    Ok((removed, removed_bytes))
+}
+
+/// Like [`read_build_manifest`], but fails if the manifest was fetched more than `max_age` ago.
+/// The fetch time of a manifest is the modification time of its stored file.
+pub(crate) async fn read_fresh_build_manifest(
+    build_number: &str,
+    product_slug: &str,
+    file_suffix: &str,
+    max_age: Duration,
+) -> tokio::io::Result<Vec<u8>> {
+    let path = manifest_path(build_number, product_slug, file_suffix);
+    let fetched_at = tokio::fs::metadata(&path).await?.modified()?;
+    if fetched_at.elapsed().unwrap_or_default() > max_age {
+        return Err(std::io::Error::other(format!(
+            "{} is older than {:?}",
+            path.display(),
+            max_age
+        )));
+    }
+
     tokio::fs::read(path).await
 }
 
+/// Parses every record of a build manifest, in order. Records without a change tag (i.e. not from
+/// a delta manifest) get an empty one.
+pub(crate) fn parse_build_manifest(
+    build_manifest_bytes: &[u8],
+) -> csv::Result<Vec<BuildManifestRecord>> {
+    let mut manifest_rdr = csv::Reader::from_reader(build_manifest_bytes);
+    manifest_rdr
+        .byte_records()
+        .map(|record| {
+            let mut record = record?;
+            if record.get(5).is_none() {
+                record.push_field(b"");
+            }
+            record.deserialize::<BuildManifestRecord>(None)
+        })
+        .collect()
+}
+
+/// Parses every record of a build manifest's chunks, in order
+pub(crate) fn parse_build_manifest_chunks(
+    build_manifest_chunks_bytes: &[u8],
+) -> csv::Result<Vec<BuildManifestChunksRecord>> {
+    let mut manifest_chunks_rdr = csv::Reader::from_reader(build_manifest_chunks_bytes);
+    manifest_chunks_rdr
+        .byte_records()
+        .map(|record| record?.deserialize::<BuildManifestChunksRecord>(None))
+        .collect()
+}
+
+/// A download shared by the chunks with the same SHA
+#[derive(Default)]
+struct SharedDownload {
+    chunk: OnceCell<Bytes>,
+    /// The bytes are kept between those chunks, so they take a memory permit of their own
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl SharedDownload {
+    fn new(permit: Option<OwnedSemaphorePermit>) -> SharedDownload {
+        SharedDownload {
+            chunk: OnceCell::new(),
+            _permit: permit,
+        }
+    }
+}
+
+/// What a build manifest asks for, worked out before anything is downloaded
+#[derive(Default)]
+struct InstallPlan {
+    /// Directories to create, as [`prepare_files`] takes them
+    directories: Vec<(String, bool, Option<u64>)>,
+    /// Files to create, as [`prepare_files`] takes them
+    files: Vec<(String, bool, Option<u64>)>,
+    excluded_files: HashSet<String>,
+    /// How many chunks each file has
+    file_chunk_num_map: HashMap<String, usize>,
+    file_size_map: HashMap<String, u64>,
+    file_sha_map: HashMap<String, String>,
+    total_bytes: u64,
+}
+
+impl InstallPlan {
+    /// Adds a record of the build manifest. Returns whether it's installed, i.e. it isn't excluded
+    /// or listed before.
+    fn add_record(
+        &mut self,
+        record: &BuildManifestRecord,
+        exclude: &[Pattern],
+    ) -> Result<bool, CarnivalError> {
+        if !record.flags.is_known() {
+            // Worth reporting, it could be the executable flag builds haven't used so far
+            debug!("{} has unknown flags {:?}", record.file_name, record.flags);
+        }
+        if is_excluded(&record.file_name, exclude) {
+            debug!("Skipping {}, it's excluded", record.file_name);
+            self.excluded_files.insert(record.file_name.clone());
+            return Ok(false);
+        }
+
+        if !record.is_directory() {
+            // Some manifests list the same file more than once. Counting it twice would throw off
+            // the chunk bookkeeping, so identical entries are only handled once.
+            match self
+                .file_sha_map
+                .insert(record.file_name.clone(), record.sha.clone())
+            {
+                Some(previous_sha) if previous_sha == record.sha => {
+                    println!(
+                        "{} is listed more than once in the manifest, skipping duplicate",
+                        record.file_name
+                    );
+                    return Ok(false);
+                }
+                Some(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "{} is listed more than once in the manifest with different contents",
+                            record.file_name
+                        ),
+                    )
+                    .into());
+                }
+                None => {}
+            }
+        }
+
+        let file_name = record.file_name.clone();
+        if record.is_directory() {
+            self.directories.push((file_name, true, None));
+        } else if record.is_empty() {
+            // Empty files are only created, there's nothing to download for them
+            self.file_chunk_num_map.insert(file_name.clone(), 0);
+            self.file_size_map.insert(file_name.clone(), 0);
+            self.files.push((file_name, false, None));
+        } else if record.chunks == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "{} has {} bytes but no chunks in the manifest",
+                    record.file_name, record.size_in_bytes
+                ),
+            )
+            .into());
+        } else {
+            let size = record.size_in_bytes as u64;
+            self.file_chunk_num_map
+                .insert(file_name.clone(), record.chunks);
+            self.file_size_map.insert(file_name.clone(), size);
+            self.total_bytes += size;
+            self.files.push((file_name, false, Some(size)));
+        }
+
+        Ok(true)
+    }
+
+    /// The chunks to download, in the order of the chunks manifest so files are mostly finished
+    /// one after another, and how many of them share each SHA. Content-identical chunks are
+    /// downloaded once.
+    fn queue_chunks(
+        &self,
+        chunk_records: Vec<BuildManifestChunksRecord>,
+    ) -> Result<(Vec<BuildManifestChunksRecord>, HashMap<String, usize>), CarnivalError> {
+        let mut queued_chunks = HashSet::new();
+        let mut chunk_sha_counts: HashMap<String, usize> = HashMap::new();
+        let mut queue = vec![];
+        for record in chunk_records {
+            if self.excluded_files.contains(&record.file_path) {
+                continue;
+            }
+            if !queued_chunks.insert((record.file_path.clone(), record.id)) {
+                println!(
+                    "Chunk {} of {} is listed more than once in the manifest, skipping duplicate",
+                    record.id, record.file_path
+                );
+                continue;
+            }
+
+            match self.file_chunk_num_map.get(&record.file_path) {
+                Some(chunks) if usize::from(record.id) < *chunks => {}
+                Some(_) if self.file_size_map[&record.file_path] == 0 => {
+                    debug!(
+                        "Skipping chunk {} of {}, the file is empty",
+                        record.id, record.file_path
+                    );
+                    continue;
+                }
+                Some(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "Chunk {} of {} is past the end of the file",
+                            record.id, record.file_path
+                        ),
+                    )
+                    .into());
+                }
+                None => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!(
+                            "Chunk {} of {} doesn't belong to any file in the manifest",
+                            record.id, record.file_path
+                        ),
+                    )
+                    .into());
+                }
+            }
+            *chunk_sha_counts.entry(record.sha.clone()).or_default() += 1;
+            queue.push(record);
+        }
+
+        Ok((queue, chunk_sha_counts))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn build_from_manifest(
     client: reqwest::Client,
     product: Arc<Product>,
     os: Arc<BuildOs>,
     build_manifest_bytes: &[u8],
     build_manifest_chunks_bytes: &[u8],
+    old_build_manifest_chunks_bytes: Option<&[u8]>,
     install_path: OsPath,
     install_opts: InstallOpts,
-) -> tokio::io::Result<bool> {
-    let mut write_queue = queue![];
+    progress: Option<ProgressSender>,
+) -> Result<(), CarnivalError> {
+    let write_workers = install_opts.write_workers.max(1);
     let mut chunk_queue = queue![];
 
     // Create install directory if it doesn't exist
     tokio::fs::create_dir_all(&install_path).await?;
 
-    let mut file_chunk_num_map = HashMap::new();
-    let mut total_bytes = 0u64;
+    let m = progress_bars();
 
-    let m = MultiProgress::new();
+    // Parsing is pure CPU work, so keep it off the async runtime. The chunks manifest is parsed
+    // while the folder structure is built.
+    let chunk_records_handle = tokio::task::spawn_blocking({
+        let build_manifest_chunks_bytes = build_manifest_chunks_bytes.to_vec();
+        move || parse_build_manifest_chunks(&build_manifest_chunks_bytes)
+    });
+    let records = tokio::task::spawn_blocking({
+        let build_manifest_bytes = build_manifest_bytes.to_vec();
+        move || parse_build_manifest(&build_manifest_bytes)
+    })
+    .await??;
+
+    // When updating, chunks that are already somewhere in the old build are copied from disk
+    // instead of downloaded again. Chunk SHAs are content hashes, so they're matched on those.
+    let mut local_chunks = HashMap::new();
+    if let Some(old_build_manifest_chunks_bytes) = old_build_manifest_chunks_bytes {
+        let old_chunk_records = tokio::task::spawn_blocking({
+            let old_build_manifest_chunks_bytes = old_build_manifest_chunks_bytes.to_vec();
+            move || parse_build_manifest_chunks(&old_build_manifest_chunks_bytes)
+        })
+        .await??;
+        for record in old_chunk_records {
+            if let Some(chunk_sha) = record.sha.rsplit('_').next() {
+                local_chunks
+                    .entry(chunk_sha.to_owned())
+                    .or_insert((record.file_path, record.id));
+            }
+        }
+    }
+    let local_chunks = Arc::new(local_chunks);
+    // Modified and removed files are moved here instead of deleted, so their chunks can still be
+    // reused. It's deleted once the update is done.
+    let old_files_path = install_path
+        .to_path()
+        .join(format!(".{}-old", *PROJECT_NAME));
+
+    let chunk_size = build_chunk_size(&records)?;
+    if chunk_size != *MAX_CHUNK_SIZE as u64 {
+        println!(
+            "This build is split into chunks of {} instead of the usual {}",
+            human_bytes(chunk_size as f64),
+            human_bytes(*MAX_CHUNK_SIZE as f64)
+        );
+    }
 
-    println!("Building folder structure...");
-    let mut manifest_rdr = csv::Reader::from_reader(build_manifest_bytes);
-    let byte_records = manifest_rdr.byte_records();
+    debug!("Building folder structure...");
     #[cfg(target_os = "macos")]
     let mut mac_app = mac::MacAppExecutables::new();
+    // Directories and files are created concurrently once the whole manifest is processed
+    let mut plan = InstallPlan::default();
 
-    for record in byte_records {
-        let mut record = record.expect("Failed to get byte record");
-        if record.get(5).is_none() {
-            record.push_field(b"");
-        }
-        let record = record
-            .deserialize::<BuildManifestRecord>(None)
-            .expect("Failed to deserialize build manifest");
-
+    for record in records {
         if record.tag == Some(ChangeTag::Modified) || record.tag == Some(ChangeTag::Removed) {
-            let file_path = install_path.join(&record.file_name);
-            println!("Removing {}", file_path);
+            let file_path = manifest_file_path(install_path.to_path(), &record.file_name);
+            debug!("Removing {}", file_path.display());
             if record.is_directory() {
-                println!("{} is a directory", file_path);
+                debug!("{} is a directory", file_path.display());
                 // Is a directory
-                if file_path.exists() && file_path.to_path().is_dir() {
-                    println!("Deleting {}", file_path);
+                if file_path.exists() && file_path.is_dir() {
+                    debug!("Deleting {}", file_path.display());
                     // Delete this directory
                     tokio::fs::remove_dir_all(file_path).await?;
                 }
                 continue;
             }
 
-            println!("{} is a file", file_path);
+            debug!("{} is a file", file_path.display());
             if file_path.exists() && file_path.is_file() {
-                println!("Deleting {}", file_path);
-                // Delete this file
-                tokio::fs::remove_file(file_path).await?;
+                if local_chunks.is_empty() {
+                    debug!("Deleting {}", file_path.display());
+                    // Delete this file
+                    tokio::fs::remove_file(file_path).await?;
+                } else {
+                    let old_file_path = manifest_file_path(&old_files_path, &record.file_name);
+                    debug!(
+                        "Moving {} to {}",
+                        file_path.display(),
+                        old_file_path.display()
+                    );
+                    if let Some(parent) = old_file_path.parent() {
+                        tokio::fs::create_dir_all(parent).await?;
+                    }
+                    tokio::fs::rename(file_path, old_file_path).await?;
+                }
             }
 
             if record.tag == Some(ChangeTag::Removed) {
@@ -358,241 +860,802 @@ pub(crate) async fn build_from_manifest(
             }
         }
 
-        prepare_file(
-            &install_path,
-            #[cfg(target_os = "macos")]
-            &os,
-            &record.file_name,
-            record.is_directory(),
-            #[cfg(target_os = "macos")]
-            &mut mac_app,
-        )
-        .await?;
+        if !plan.add_record(&record, &install_opts.exclude)? {
+            continue;
+        }
 
-        if !record.is_directory() {
-            file_chunk_num_map.insert(record.file_name.clone(), record.chunks);
-            total_bytes += record.size_in_bytes as u64;
+        #[cfg(target_os = "macos")]
+        if *os == BuildOs::Mac {
+            mac_app.add_record(install_path.to_path(), &record);
         }
     }
-
-    let dl_sty =
-        ProgressStyle::with_template("{wide_msg} Download: {binary_bytes_per_sec}").unwrap();
-    let wr_sty = ProgressStyle::with_template(
-        "{wide_msg} Disk: {binary_bytes_per_sec}\n[{percent}%] {wide_bar} {bytes:>7}/{total_bytes:7} [{eta_precise}]",
+    // Every directory has to exist before the files in it are created
+    prepare_files(&install_path, std::mem::take(&mut plan.directories)).await?;
+    prepare_files(&install_path, std::mem::take(&mut plan.files)).await?;
+
+    // The totals are drawn by whoever gets the progress events, the CLI's own bars included
+    report(progress.as_ref(), ProgressEvent::Started(plan.total_bytes)).await;
+    let started = Instant::now();
+    let downloaded_bytes = Arc::new(AtomicU64::new(0));
+    let written_bytes = Arc::new(AtomicU64::new(0));
+    let file_sty = ProgressStyle::with_template(
+        "{wide_msg} {bytes:>7}/{total_bytes:7}\n[{percent}%] {wide_bar}",
     )
     .unwrap()
     .progress_chars("##-");
+    // Show the file each write worker is currently writing
+    let mut file_progs: Vec<ProgressBar> = Vec::with_capacity(write_workers);
+    for _ in 0..write_workers {
+        file_progs.push(m.add(ProgressBar::new(0).with_style(file_sty.clone())));
+    }
 
-    let dl_prog = Arc::new(m.add(ProgressBar::new(total_bytes).with_style(dl_sty)));
-    let wrt_prog =
-        Arc::new(m.insert_after(&dl_prog, ProgressBar::new(total_bytes).with_style(wr_sty)));
-
-    println!("Building queue...");
-    let mut manifest_chunks_rdr = csv::Reader::from_reader(build_manifest_chunks_bytes);
-    let byte_records = manifest_chunks_rdr.byte_records();
-    for record in byte_records {
-        let record = record.expect("Failed to get byte record");
-        let record = record
-            .deserialize::<BuildManifestChunksRecord>(None)
-            .expect("Failed to deserialize chunks manifest");
-
-        let is_last = file_chunk_num_map[&record.file_path] - 1 == usize::from(record.id);
-        if is_last {
-            file_chunk_num_map.remove(&record.file_path);
-        }
-        write_queue
-            .add((record.sha.clone(), record.id, is_last))
-            .unwrap();
+    debug!("Building queue...");
+    let (queued_chunks, mut chunk_sha_counts) = plan.queue_chunks(chunk_records_handle.await??)?;
+    for record in queued_chunks {
         chunk_queue.add(record).unwrap();
     }
-    drop(file_chunk_num_map);
-
+    let InstallPlan {
+        file_chunk_num_map,
+        file_size_map,
+        ..
+    } = plan;
+    // How many chunks of each file are left to write, and how many bytes were written so far.
+    // Chunk ids are unique and in range, so a file is complete once it has none left.
+    let file_chunks: Arc<Mutex<HashMap<String, (usize, u64)>>> = Arc::new(Mutex::new(
+        file_chunk_num_map
+            .into_iter()
+            .map(|(file_path, chunks)| (file_path, (chunks, 0)))
+            .collect(),
+    ));
+    let file_size_map = Arc::new(file_size_map);
+
+    // Every chunk is written at its own offset, so any write worker can take the next one
     let (tx, rx) =
         async_channel::unbounded::<(BuildManifestChunksRecord, Bytes, OwnedSemaphorePermit)>();
 
-    println!("Spawning write thread...");
-    let write_handler = tokio::spawn(async move {
-        println!("Write thread started.");
+    // Only used for the download stats at the trace log level
+    let active_downloads = Arc::new(AtomicUsize::new(0));
+    let reused_bytes = Arc::new(AtomicUsize::new(0));
+    let deduplicated_bytes = Arc::new(AtomicUsize::new(0));
+    // Downloads stop once a write worker fails, there's no point in downloading what can't be
+    // written
+    let write_failed = Arc::new(AtomicBool::new(false));
+    // Downloads also stop once one of them fails, the install fails either way
+    let download_failed = Arc::new(AtomicBool::new(false));
+
+    debug!("Spawning {write_workers} write workers...");
+    let mut write_handlers = Vec::with_capacity(write_workers);
+    for file_prog in file_progs {
+        let rx = rx.clone();
+        let written_bytes = written_bytes.clone();
+        let progress = progress.clone();
+        let file_size_map = file_size_map.clone();
+        let file_chunks = file_chunks.clone();
+        let write_install_path = install_path.clone();
+        let write_failed = write_failed.clone();
+        write_handlers.push(tokio::spawn(async move {
+            let result = async {
+                debug!("Write worker started.");
+
+                // Once every download is done, the channel closes. Chunks that never arrived leave
+                // their file unfinished, and the downloads report why.
+                while let Ok((record, chunk, permit)) = rx.recv().await {
+                    let file_size = file_size_map
+                        .get(&record.file_path)
+                        .copied()
+                        .unwrap_or_default();
+                    let offset = chunk_offset(record.id, chunk_size);
+                    let final_path =
+                        manifest_file_path(write_install_path.to_path(), &record.file_path);
+                    trace!("Writing {}", record.sha);
+                    write_chunk(&part_file_path(&final_path), offset, &chunk).await?;
+                    let bytes_written = chunk.len() as u64;
+                    drop(chunk);
+                    drop(permit);
+
+                    written_bytes.fetch_add(bytes_written, Ordering::Relaxed);
+                    report(progress.as_ref(), ProgressEvent::Wrote(bytes_written)).await;
+
+                    let (file_written, complete) = {
+                        let mut file_chunks = file_chunks.lock().unwrap();
+                        // Only queued chunks are written, so this can't happen unless the
+                        // bookkeeping above is wrong. Better to fail than to rename a broken file.
+                        let Some((chunks_left, file_written)) = file_chunks
+                            .get_mut(&record.file_path)
+                            .filter(|(chunks_left, _)| *chunks_left > 0)
+                        else {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                format!("Got more chunks of {} than it has", record.file_path),
+                            )
+                            .into());
+                        };
+                        *chunks_left -= 1;
+                        *file_written += bytes_written;
+                        (*file_written, *chunks_left == 0)
+                    };
+                    file_prog.set_message(record.file_path.clone());
+                    file_prog.set_length(file_size);
+                    file_prog.set_position(file_written);
+
+                    if complete {
+                        // The file only gets its real name once it's complete, so a leftover
+                        // .part file is always an unfinished one
+                        tokio::fs::rename(part_file_path(&final_path), &final_path).await?;
+                    }
+                }
+                debug!("Write worker finished.");
 
-        let mut in_buffer = HashMap::new();
-        let mut file_map = HashMap::new();
+                Ok::<(), CarnivalError>(())
+            }
+            .await;
+            file_prog.finish_and_clear();
+            if result.is_err() {
+                write_failed.store(true, Ordering::Relaxed);
+                // Nothing else is written, so the downloads waiting for memory can stop too
+                rx.close();
+            }
 
-        while write_queue.size() > 0 {
-            let (record, chunk, permit) = match rx.recv().await {
-                Ok(msg) => msg,
-                Err(_) => {
-                    println!("Write channel has closed");
+            result
+        }));
+    }
+    drop(rx);
+
+    debug!("Downloading chunks...");
+    // Every chunk takes a permit before it's downloaded (or read from disk) and gives it back once
+    // it's written, so chunks waiting for a write worker count against the budget too. A slow disk
+    // only stalls downloads instead of piling up chunks. At least one chunk has to fit, or nothing
+    // would ever download.
+    let max_chunks_in_memory = (install_opts.max_memory_usage as u64 / chunk_size).max(1) as usize;
+    let mem_semaphore = Arc::new(Semaphore::new(max_chunks_in_memory));
+    let max_download_workers = install_opts.max_download_workers.max(1);
+    // Adds download workers over time. Dropping it stops that, however this function returns.
+    let mut ramp_up = JoinSet::new();
+    let dl_semaphore = if install_opts.auto_tune {
+        // Start with a single worker and double them while that keeps making downloads faster.
+        // What works best depends on the connection's latency more than on anything we know.
+        let dl_semaphore = Arc::new(Semaphore::new(1));
+        let tune_semaphore = dl_semaphore.clone();
+        let downloaded_bytes = downloaded_bytes.clone();
+        ramp_up.spawn(async move {
+            let mut workers = 1;
+            let mut best_speed = 0f64;
+            let mut last_position = downloaded_bytes.load(Ordering::Relaxed);
+            while workers < max_download_workers {
+                tokio::time::sleep(*AUTO_TUNE_WINDOW).await;
+                let position = downloaded_bytes.load(Ordering::Relaxed);
+                let speed = (position - last_position) as f64 / AUTO_TUNE_WINDOW.as_secs_f64();
+                last_position = position;
+                // Nothing was downloaded, e.g. because chunks are reused or downloads are paused
+                if speed == 0f64 {
+                    continue;
+                }
+                if speed < best_speed * *AUTO_TUNE_MIN_SPEEDUP {
+                    debug!(
+                        "Downloads stopped getting faster, using {workers} download workers ({}/s)",
+                        human_bytes(best_speed)
+                    );
                     break;
                 }
-            };
-
-            // Some files don't have the chunk id in the sha parts, so they can have reused
-            // SHAs for chunks (e.g. DieYoungPrologue-WindowsNoEditor.pak)
-            let chunk_key = format!("{},{}", record.id, record.sha);
-            in_buffer.insert(chunk_key, (record.file_path, chunk, permit));
 
+                best_speed = best_speed.max(speed);
+                let new_workers = workers.min(max_download_workers - workers);
+                trace!(
+                    "Adding {new_workers} download workers at {}/s",
+                    human_bytes(speed)
+                );
+                tune_semaphore.add_permits(new_workers);
+                workers += new_workers;
+            }
+        });
+        dl_semaphore
+    } else if install_opts.download_ramp_up.is_zero() {
+        Arc::new(Semaphore::new(max_download_workers))
+    } else {
+        // Start with a single worker and double them every `download_ramp_up`, so the CDN isn't
+        // hit by every worker at once
+        let dl_semaphore = Arc::new(Semaphore::new(1));
+        let ramp_up_semaphore = dl_semaphore.clone();
+        ramp_up.spawn(async move {
+            let mut workers = 1;
+            while workers < max_download_workers {
+                tokio::time::sleep(install_opts.download_ramp_up).await;
+                let new_workers = workers.min(max_download_workers - workers);
+                trace!("Adding {new_workers} download workers");
+                ramp_up_semaphore.add_permits(new_workers);
+                workers += new_workers;
+            }
+        });
+        dl_semaphore
+    };
+    let stats_handler = if log_enabled!(Level::Trace) {
+        let mem_semaphore = mem_semaphore.clone();
+        let active_downloads = active_downloads.clone();
+        // Weak sender, so the stats don't keep the channel open
+        let weak_tx = tx.downgrade();
+        let downloaded_bytes = downloaded_bytes.clone();
+        let written_bytes = written_bytes.clone();
+
+        Some(tokio::spawn(async move {
+            let period = Duration::from_secs(5);
+            let mut interval = tokio::time::interval(period);
+            // The first tick completes right away
+            interval.tick().await;
+            let mut last_downloaded = 0;
+            let mut last_written = 0;
             loop {
-                match write_queue.peek() {
-                    Ok((next_chunk, chunk_id, is_last_chunk)) => {
-                        let next_chunk_key = format!("{},{}", chunk_id, next_chunk);
-                        if let Some((file_path, bytes, permit)) = in_buffer.remove(&next_chunk_key)
-                        {
-                            if !file_map.contains_key(&file_path) {
-                                let chunk_file_path = install_path.join(&file_path);
-                                let file = open_file(&chunk_file_path).await.unwrap_or_else(|_| {
-                                    panic!("Failed to open {}", chunk_file_path)
-                                });
-                                file_map.insert(file_path.clone(), file);
-                            }
-                            let file = file_map.get_mut(&file_path).unwrap();
-                            write_queue.remove().unwrap();
-                            // println!("Writing {}", next_chunk);
-                            let bytes_written = bytes.len();
-                            append_chunk(file, bytes).await.unwrap_or_else(|_| {
-                                panic!("Failed to write {}.bin to {}", next_chunk, file_path)
-                            });
-                            drop(permit);
-
-                            wrt_prog.inc(bytes_written as u64);
-
-                            if is_last_chunk {
-                                file_map.remove(&file_path);
-                            }
-
-                            continue;
-                        }
-
-                        // println!(
-                        //     "Not ready to write {}: {} pending",
-                        //     next_chunk,
-                        //     in_buffer.len()
-                        // );
-
-                        break;
-                    }
-                    Err(_) => {
-                        println!("No more chunks to write");
-                        return;
-                    }
-                }
+                interval.tick().await;
+                let downloaded = downloaded_bytes.load(Ordering::Relaxed);
+                let written = written_bytes.load(Ordering::Relaxed);
+                // Chunks hold on to their memory permit until they're written to disk, and shared
+                // downloads until the last chunk with their SHA is queued
+                let chunks_in_flight = max_chunks_in_memory - mem_semaphore.available_permits();
+                m.println(format!(
+                    "Workers: {} downloading | Chunks in flight: {} | Write backlog: {} | Network: {}/s | Disk: {}/s",
+                    active_downloads.load(Ordering::Relaxed),
+                    chunks_in_flight,
+                    weak_tx.upgrade().map_or(0, |tx| tx.len()),
+                    human_bytes((downloaded - last_downloaded) as f64 / period.as_secs_f64()),
+                    human_bytes((written - last_written) as f64 / period.as_secs_f64()),
+                ))
+                .ok();
+                last_downloaded = downloaded;
+                last_written = written;
             }
+        }))
+    } else {
+        None
+    };
+    // Kept until the downloads are done, so prompts after them get their input again
+    let pause_listener = PauseListener::new();
+    let paused = pause_listener.paused();
+    let mut download_handles = vec![];
+    // Chunks with the same SHA share one download. Every chunk still takes its own memory permit
+    // in queue order, and the first one to need the bytes downloads them, so the others only wait
+    // for a download that already has a permit. The bytes are dropped once the last chunk with
+    // that SHA is done with them.
+    let mut shared_downloads: HashMap<String, Arc<SharedDownload>> = HashMap::new();
+    while let Ok(record) = chunk_queue.remove() {
+        // No point in downloading more if a write worker failed (e.g. a failed write), or a
+        // download did (e.g. a corrupted chunk)
+        if write_failed.load(Ordering::Relaxed) || download_failed.load(Ordering::Relaxed) {
+            break;
         }
-        println!("Write thread finished.");
-    });
 
-    println!("Downloading chunks...");
-    let max_chunks_in_memory = install_opts.max_memory_usage / *MAX_CHUNK_SIZE;
-    let mem_semaphore = Arc::new(Semaphore::new(max_chunks_in_memory));
-    let dl_semaphore = Arc::new(Semaphore::new(install_opts.max_download_workers));
-    while let Ok(record) = chunk_queue.remove() {
         let mem_permit = mem_semaphore.clone().acquire_owned().await.unwrap();
         let client = client.clone();
         let product = product.clone();
         let os = os.clone();
         let thread_tx = tx.clone();
-        let dl_prog = dl_prog.clone();
+        let download_failed = download_failed.clone();
+        let downloaded_bytes = downloaded_bytes.clone();
+        let progress = progress.clone();
         let dl_semaphore = dl_semaphore.clone();
-
-        tokio::spawn(async move {
-            // println!("Downloading {}", record.sha);
-            let dl_permit = dl_semaphore.acquire().await.unwrap();
-            let chunk = api::product::download_chunk(&client, &product, &os, &record.sha)
-                .await
-                .unwrap_or_else(|_| panic!("Failed to download {}.bin", &record.sha));
-            drop(dl_permit);
-
-            dl_prog.inc(chunk.len() as u64);
-
-            if !install_opts.skip_verify {
-                let chunk_parts = &record.sha.split('_').collect::<Vec<&str>>();
-                match chunk_parts.last() {
-                    Some(chunk_sha) => {
-                        // println!("Verifying {}", record.sha);
-                        let chunk_corrupted = !verify_chunk(&chunk, chunk_sha);
-
-                        if chunk_corrupted {
-                            println!("Sha: {}", chunk_sha);
-                            println!(
-                                "{} failed verification. {} is corrupted.",
-                                &record.sha, &record.file_path
-                            );
-                            return false;
-                        }
+        let active_downloads = active_downloads.clone();
+        let local_chunks = local_chunks.clone();
+        let install_path = install_path.clone();
+        let old_files_path = old_files_path.clone();
+        let reused_bytes = reused_bytes.clone();
+        let deduplicated_bytes = deduplicated_bytes.clone();
+        let expected_len = expected_chunk_len(
+            file_size_map
+                .get(&record.file_path)
+                .copied()
+                .unwrap_or_default(),
+            record.id,
+            chunk_size,
+        );
+        let mut paused = paused.clone();
+        let shared_download = match chunk_sha_counts.get_mut(&record.sha) {
+            Some(remaining) if *remaining > 1 => {
+                *remaining -= 1;
+                match shared_downloads.entry(record.sha.clone()) {
+                    Entry::Occupied(entry) => Some(entry.get().clone()),
+                    // Keeping the bytes until the next chunk with this SHA takes memory too. Only
+                    // the main loop takes permits, so leaving one for the chunks in between means
+                    // there's always a chunk in flight that gives its permit back. Otherwise the
+                    // bytes are downloaded again.
+                    Entry::Vacant(entry) if mem_semaphore.available_permits() > 1 => {
+                        let permit = mem_semaphore.clone().try_acquire_owned().ok();
+                        Some(entry.insert(Arc::new(SharedDownload::new(permit))).clone())
                     }
-                    None => {
-                        println!("Couldn't find Chunk SHA. Skipping verification...");
+                    Entry::Vacant(_) => None,
+                }
+            }
+            _ => shared_downloads.remove(&record.sha),
+        }
+        .unwrap_or_default();
+
+        download_handles.push(tokio::spawn(async move {
+            let result = async {
+                let chunk_sha = record.sha.rsplit('_').next().unwrap_or_default();
+                if let Some((file_path, id)) = local_chunks.get(chunk_sha) {
+                    // Modified files were moved out of the way, and unchanged ones are still in place
+                    let old_file_path = match manifest_file_path(&old_files_path, file_path) {
+                        path if path.exists() => path,
+                        _ => manifest_file_path(install_path.to_path(), file_path),
+                    };
+                    match read_local_chunk(&old_file_path, *id, chunk_size).await {
+                        Ok(chunk) if verify_chunk(&chunk, chunk_sha) => {
+                            trace!("Reusing {} from {}", record.sha, old_file_path.display());
+                            reused_bytes.fetch_add(chunk.len(), Ordering::Relaxed);
+                            thread_tx.send((record, chunk, mem_permit)).await.ok();
+                            return Ok(());
+                        }
+                        _ => {
+                            trace!("Couldn't reuse {}, downloading it instead", record.sha);
+                        }
                     }
                 }
+
+                let mut downloaded = false;
+                // If the download fails, the next chunk waiting for it tries again
+                let chunk = shared_download
+                    .chunk
+                    .get_or_try_init(|| async {
+                        downloaded = true;
+                        // Chunks that are already being downloaded finish, and get written to disk
+                        paused.wait_for(|paused| !paused).await.ok();
+                        trace!("Downloading {}", record.sha);
+                        let dl_permit = dl_semaphore.acquire().await.unwrap();
+                        active_downloads.fetch_add(1, Ordering::Relaxed);
+                        let chunk =
+                            api::product::download_chunk(&client, &product, &os, &record.sha).await;
+                        active_downloads.fetch_sub(1, Ordering::Relaxed);
+                        drop(dl_permit);
+                        let chunk = chunk?;
+
+                        downloaded_bytes.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                        report(
+                            progress.as_ref(),
+                            ProgressEvent::Downloaded(chunk.len() as u64),
+                        )
+                        .await;
+
+                        // Chunks are written at an offset that assumes they have the right length, and
+                        // e.g. an error page or a cut off download is caught sooner than by hashing it
+                        if chunk.len() as u64 != expected_len {
+                            return Err(CarnivalError::ChunkSize {
+                                chunk: record.sha.clone(),
+                                file_path: record.file_path.clone(),
+                                expected: expected_len,
+                                actual: chunk.len() as u64,
+                            });
+                        }
+
+                        if !install_opts.skip_verify {
+                            let chunk_parts = &record.sha.split('_').collect::<Vec<&str>>();
+                            match chunk_parts.last() {
+                                Some(chunk_sha) => {
+                                    trace!("Verifying {}", record.sha);
+                                    let chunk_corrupted = !verify_chunk(&chunk, chunk_sha);
+
+                                    if chunk_corrupted {
+                                        debug!("Expected sha: {}", chunk_sha);
+                                        match failed_chunks::record(&FailedChunk {
+                                            timestamp: Local::now(),
+                                            slug: &product.slugged_name,
+                                            os: os.to_string(),
+                                            file_path: &record.file_path,
+                                            chunk: &record.sha,
+                                            expected_sha: chunk_sha,
+                                            computed_sha: chunk_sha256(&chunk),
+                                            size: chunk.len(),
+                                        }) {
+                                            Ok(()) if !is_quiet() => {
+                                                progress_bars().suspend(|| {
+                                                    println!(
+                                                        "Saved the details of {} to {}",
+                                                        record.sha,
+                                                        failed_chunks_path().display()
+                                                    )
+                                                });
+                                            }
+                                            Ok(()) => {}
+                                            Err(err) => {
+                                                warn!("Failed to log the failed chunk: {:?}", err)
+                                            }
+                                        }
+                                        return Err(CarnivalError::Verification {
+                                            chunk: record.sha.clone(),
+                                            file_path: record.file_path.clone(),
+                                        });
+                                    }
+                                }
+                                None => {
+                                    println!("Couldn't find Chunk SHA. Skipping verification...");
+                                }
+                            }
+                        }
+
+                        Ok(chunk)
+                    })
+                    .await?
+                    .clone();
+                drop(shared_download);
+                if !downloaded {
+                    trace!("Reusing the download of {}", record.sha);
+                    deduplicated_bytes.fetch_add(chunk.len(), Ordering::Relaxed);
+                }
+
+                // This only fails if a write worker failed, which it reports itself
+                thread_tx.send((record, chunk, mem_permit)).await.ok();
+
+                Ok::<(), CarnivalError>(())
+            }
+            .await;
+            if result.is_err() {
+                download_failed.store(true, Ordering::Relaxed);
             }
 
-            thread_tx.send((record, chunk, mem_permit)).await.unwrap();
+            result
+        }));
+    }
+    // Let the write workers know once every download is done
+    drop(tx);
+
+    let mut download_result = Ok(());
+    for handle in download_handles {
+        if let Err(err) = handle.await? {
+            // Keep the first error, it's usually the cause of the others
+            if download_result.is_ok() {
+                download_result = Err(err);
+            }
+        }
+    }
 
-            true
-        });
+    debug!("Waiting for write workers to finish...");
+    let mut write_result = Ok(());
+    for write_handler in write_handlers {
+        if let Err(err) = write_handler.await? {
+            if write_result.is_ok() {
+                write_result = Err(err);
+            }
+        }
     }
 
-    println!("Waiting for write thread to finish...");
-    write_handler.await?;
+    if let Some(stats_handler) = stats_handler {
+        stats_handler.abort();
+    }
+    if old_files_path.exists() {
+        debug!("Deleting {}", old_files_path.display());
+        if let Err(err) = tokio::fs::remove_dir_all(&old_files_path).await {
+            println!("Failed to delete {}: {}", old_files_path.display(), err);
+        }
+    }
+    let reused_bytes = reused_bytes.load(Ordering::Relaxed);
+    if reused_bytes > 0 {
+        println!(
+            "Reused {} from the existing install instead of downloading it",
+            human_bytes(reused_bytes as f64)
+        );
+    }
+    let deduplicated_bytes = deduplicated_bytes.load(Ordering::Relaxed);
+    if deduplicated_bytes > 0 {
+        println!(
+            "Skipped downloading {} of chunks that are repeated in the build",
+            human_bytes(deduplicated_bytes as f64)
+        );
+    }
+
+    download_result?;
+    write_result?;
+
+    // Chunks are verified when they're downloaded, but a file could still have been cut short
+    // while writing it
+    debug!("Checking file sizes...");
+    for (file_name, &expected) in file_size_map.iter() {
+        let file_path = manifest_file_path(install_path.to_path(), file_name);
+        // A file that's still missing never got its last chunk written
+        let actual = match tokio::fs::metadata(&file_path).await {
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        };
+        if actual != expected {
+            return Err(CarnivalError::IncompleteFile {
+                file_path: file_name.clone(),
+                expected,
+                actual,
+            });
+        }
+    }
 
     #[cfg(target_os = "macos")]
     if *os == BuildOs::Mac {
         mac_app.mark_as_executable().await?;
     }
 
-    // TODO: Redo logic for verification
-    Ok(true)
+    let downloaded_bytes = downloaded_bytes.load(Ordering::Relaxed);
+    let elapsed = started.elapsed();
+    let average_speed = match elapsed.as_secs_f64() {
+        secs if secs > 0f64 => downloaded_bytes as f64 / secs,
+        _ => 0f64,
+    };
+    println!(
+        "Downloaded {} in {} ({}/s)",
+        human_bytes(downloaded_bytes as f64),
+        HumanDuration(elapsed),
+        human_bytes(average_speed)
+    );
+    match dir_size(install_path.to_path()).await {
+        Ok(size) => println!("{} takes up {}", install_path, human_bytes(size as f64)),
+        Err(err) => debug!("Failed to get the size of {}: {:?}", install_path, err),
+    }
+
+    Ok(())
 }
 
-pub(crate) async fn open_file(file_path: &OsPath) -> tokio::io::Result<File> {
-    tokio::fs::OpenOptions::new()
-        .append(true)
+/// Checks if a file from a build manifest, or a directory it's in, matches one of the `--exclude`
+/// patterns. Both slashes are matched as `/`.
+pub(crate) fn is_excluded(file_name: &str, exclude: &[Pattern]) -> bool {
+    if exclude.is_empty() {
+        return false;
+    }
+
+    let file_name = file_name.replace('\\', "/");
+    std::iter::once(file_name.as_str())
+        .chain(file_name.match_indices('/').map(|(i, _)| &file_name[..i]))
+        .any(|path| exclude.iter().any(|pattern| pattern.matches(path)))
+}
+
+/// The size of the chunks a build is split into. Builds are usually split into [`MAX_CHUNK_SIZE`]
+/// chunks, but the manifest doesn't say, so it's worked out from how many chunks each file has.
+/// Fails if no chunk size fits every file, or if it can't be told apart from others that do.
+fn build_chunk_size(records: &[BuildManifestRecord]) -> Result<u64, CarnivalError> {
+    // Every file with N chunks and S bytes needs a chunk size in ceil(S / N)..=(S - 1) / (N - 1)
+    let mut min = 1u64;
+    let mut max = u64::MAX;
+    for record in records {
+        if record.is_directory() || record.chunks == 0 || record.is_empty() {
+            continue;
+        }
+
+        let size = record.size_in_bytes as u64;
+        let chunks = record.chunks as u64;
+        min = min.max(size.div_ceil(chunks));
+        if chunks > 1 {
+            max = max.min((size - 1) / (chunks - 1));
+        }
+    }
+
+    let default = *MAX_CHUNK_SIZE as u64;
+    if (min..=max).contains(&default) {
+        return Ok(default);
+    }
+    // Chunk sizes are powers of two, so another one is the most likely
+    match min.checked_next_power_of_two() {
+        Some(chunk_size) if chunk_size <= max => Ok(chunk_size),
+        _ if min == max => Ok(min),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "Couldn't work out the chunk size of this build from its manifest",
+        )
+        .into()),
+    }
+}
+
+/// Where chunk `id` starts in its file. Every chunk but the last one of a file is `chunk_size`
+/// long.
+fn chunk_offset(id: u16, chunk_size: u64) -> u64 {
+    u64::from(id) * chunk_size
+}
+
+/// How long chunk `id` of a file that's `file_size` bytes is. The last chunk is whatever is left
+/// of the file.
+fn expected_chunk_len(file_size: u64, id: u16, chunk_size: u64) -> u64 {
+    file_size
+        .saturating_sub(chunk_offset(id, chunk_size))
+        .min(chunk_size)
+}
+
+/// How many bytes updating to a build downloads. Chunks that are already in the old build are
+/// reused instead, and chunks repeated in the new build are only downloaded once.
+pub(crate) fn delta_download_size(
+    new_manifest: &[u8],
+    new_manifest_chunks: &[u8],
+    old_manifest_chunks: &[u8],
+) -> Result<u64, CarnivalError> {
+    let records = parse_build_manifest(new_manifest)?;
+    let chunk_size = build_chunk_size(&records)?;
+    let file_sizes: HashMap<String, u64> = records
+        .into_iter()
+        .map(|record| (record.file_name, record.size_in_bytes as u64))
+        .collect();
+    let old_chunks: HashSet<String> = parse_build_manifest_chunks(old_manifest_chunks)?
+        .into_iter()
+        .filter_map(|record| Some(record.sha.rsplit('_').next()?.to_owned()))
+        .collect();
+
+    let mut downloaded = HashSet::new();
+    let mut download_size = 0;
+    for record in parse_build_manifest_chunks(new_manifest_chunks)? {
+        let chunk_sha = record.sha.rsplit('_').next().unwrap_or_default();
+        if old_chunks.contains(chunk_sha) || !downloaded.insert(record.sha.clone()) {
+            continue;
+        }
+        let file_size = file_sizes
+            .get(&record.file_path)
+            .copied()
+            .unwrap_or_default();
+        download_size += expected_chunk_len(file_size, record.id, chunk_size);
+    }
+
+    Ok(download_size)
+}
+
+/// Reads chunk `id` of an existing file. If the file was split into chunks of another size, the
+/// chunk won't match its SHA.
+async fn read_local_chunk(file_path: &Path, id: u16, chunk_size: u64) -> tokio::io::Result<Bytes> {
+    let mut file = File::open(file_path).await?;
+    file.seek(std::io::SeekFrom::Start(chunk_offset(id, chunk_size)))
+        .await?;
+    let mut chunk = Vec::with_capacity(chunk_size as usize);
+    file.take(chunk_size).read_to_end(&mut chunk).await?;
+
+    Ok(Bytes::from(chunk))
+}
+
+/// Path a file is written to while it's being downloaded, before it's renamed to `file_path`
+pub(crate) fn part_file_path(file_path: &Path) -> PathBuf {
+    let mut part_path = file_path.to_path_buf().into_os_string();
+    part_path.push(".part");
+    PathBuf::from(part_path)
+}
+
+/// Where the file `file_name` from a build manifest goes in `base_path`. Manifests can use either
+/// slash, and their file names are read as latin1, so on Unix each char is turned back into the
+/// byte it was stored as instead of being encoded as UTF-8. That way, non-ASCII file names end up
+/// exactly as they are in the manifest.
+pub(crate) fn manifest_file_path(base_path: &Path, file_name: &str) -> PathBuf {
+    file_name
+        .split(['/', '\\'])
+        .filter(|component| !component.is_empty())
+        .fold(base_path.to_path_buf(), |path, component| {
+            path.join(manifest_path_component(component))
+        })
+}
+
+#[cfg(unix)]
+fn manifest_path_component(component: &str) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+
+    let bytes: Vec<u8> = component.chars().map(|c| c as u8).collect();
+    // macOS only allows UTF-8 file names
+    #[cfg(target_os = "macos")]
+    if std::str::from_utf8(&bytes).is_err() {
+        return OsString::from(component);
+    }
+
+    OsString::from_vec(bytes)
+}
+
+/// Windows file names are UTF-16, which every latin1 char maps to as is
+#[cfg(not(unix))]
+fn manifest_path_component(component: &str) -> OsString {
+    OsString::from(component)
+}
+
+/// Writes a chunk at `offset` of a file created by [`prepare_file`]. It's usually preallocated
+/// already, so chunks fill in the reserved space in whatever order they arrive.
+async fn write_chunk(file_path: &Path, offset: u64, chunk: &[u8]) -> tokio::io::Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
         .open(file_path)
-        .await
+        .await?;
+    file.seek(std::io::SeekFrom::Start(offset)).await?;
+    file.write_all(chunk).await?;
+    // Writes only finish in the background otherwise, and errors would go unnoticed
+    file.flush().await
 }
 
-pub(crate) async fn append_chunk(
-    file: &mut tokio::fs::File,
-    chunk: Bytes,
-) -> tokio::io::Result<()> {
-    file.write_all(&chunk).await
+/// Runs [`prepare_file`] for every `(file_name, is_directory, chunked_size)` entry, a bunch of
+/// them at a time
+async fn prepare_files(
+    base_install_path: &OsPath,
+    entries: Vec<(String, bool, Option<u64>)>,
+) -> Result<(), CarnivalError> {
+    let semaphore = Arc::new(Semaphore::new(64));
+    let mut handles = JoinSet::new();
+    for (file_name, is_directory, chunked_size) in entries {
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        let base_install_path = base_install_path.clone();
+        handles.spawn(async move {
+            let result =
+                prepare_file(&base_install_path, &file_name, is_directory, chunked_size).await;
+            drop(permit);
+            result
+        });
+    }
+
+    while let Some(result) = handles.join_next().await {
+        result??;
+    }
+
+    Ok(())
 }
 
+/// Creates a directory or an empty file from the manifest. `chunked_size` is the size of files
+/// that are written chunk by chunk, which get that much space reserved up front.
 pub(crate) async fn prepare_file(
     base_install_path: &OsPath,
-    #[cfg(target_os = "macos")] os: &BuildOs,
-    file_name: &String,
+    file_name: &str,
     is_directory: bool,
-    #[cfg(target_os = "macos")] mac_executable: &mut mac::MacAppExecutables,
+    chunked_size: Option<u64>,
 ) -> tokio::io::Result<()> {
-    let file_path = base_install_path.join(file_name);
+    let file_path = manifest_file_path(base_install_path.to_path(), file_name);
 
-    // File is a directory. We should create this directory.
+    // File is a directory. We should create this directory. Its parents might still be being
+    // created, which create_dir_all is fine with.
     if is_directory {
-        if !file_path.exists() {
-            tokio::fs::create_dir(&file_path).await?;
+        tokio::fs::create_dir_all(&file_path).await?;
+    } else if let Some(size) = chunked_size {
+        // Create empty file to write the chunks to. It's renamed once the last one is written.
+        let file = tokio::fs::File::create(part_file_path(&file_path))
+            .await?
+            .into_std()
+            .await;
+        // Reserving the whole file at once keeps big files from getting fragmented while they
+        // grow chunk by chunk. Not every filesystem supports it, and writing works without it.
+        let allocated = tokio::task::spawn_blocking(move || fs2::FileExt::allocate(&file, size))
+            .await
+            .map_err(std::io::Error::other)?;
+        if let Err(err) = allocated {
+            debug!("Couldn't preallocate {}: {}", file_path.display(), err);
         }
     } else {
         // Create empty file.
         tokio::fs::File::create(&file_path).await?;
     }
 
-    #[cfg(target_os = "macos")]
-    if os == &BuildOs::Mac && mac_executable.plist.is_none() {
-        if let Some(ext) = file_path.extension() {
-            if &ext == "app" {
-                let plist = mac::find_info_plist(&file_path.to_pathbuf());
-                mac_executable.set_plist(plist);
-            }
-        };
+    Ok(())
+}
+
+/// Sums the size of every file in a build manifest, which is the disk space the build takes up
+/// once installed
+pub(crate) fn manifest_disk_size(build_manifest_bytes: &[u8]) -> Result<f64, CarnivalError> {
+    Ok(parse_build_manifest(build_manifest_bytes)?
+        .iter()
+        .map(|record| record.size_in_bytes as f64)
+        .sum())
+}
+
+/// Cheaply checks whether the files in `install_path` could belong to the build described by
+/// `build_manifest_bytes`, by comparing file sizes without hashing anything.
+pub(crate) async fn file_sizes_match_manifest(
+    install_path: &OsPath,
+    build_manifest_bytes: &[u8],
+) -> bool {
+    let Ok(records) = parse_build_manifest(build_manifest_bytes) else {
+        return false;
+    };
+    for record in records {
+        if record.is_directory() {
+            continue;
+        }
+
+        match tokio::fs::metadata(manifest_file_path(
+            install_path.to_path(),
+            &record.file_name,
+        ))
+        .await
+        {
+            Ok(metadata) if metadata.len() == record.size_in_bytes as u64 => {}
+            _ => return false,
+        }
     }
 
-    Ok(())
+    true
 }
 
-pub(crate) fn verify_file_hash(file_path: &OsPath, sha: &str) -> std::io::Result<bool> {
+/// Checks a file against `sha`, adding the bytes hashed so far to `progress`
+pub(crate) fn verify_file_hash(
+    file_path: &Path,
+    sha: &str,
+    progress: &ProgressBar,
+) -> std::io::Result<bool> {
     let mut file = std::fs::File::open(file_path)?;
     let mut hasher = Sha256::new();
-    std::io::copy(&mut file, &mut hasher)?;
+    std::io::copy(&mut file, &mut progress.wrap_write(&mut hasher))?;
     let hash = hasher.finalize();
     let file_sha = base16ct::lower::encode_string(&hash);
 
@@ -600,12 +1663,171 @@ pub(crate) fn verify_file_hash(file_path: &OsPath, sha: &str) -> std::io::Result
 }
 
 pub(crate) fn verify_chunk(chunk: &Bytes, sha: &str) -> bool {
+    chunk_sha256(chunk) == sha
+}
+
+fn chunk_sha256(chunk: &Bytes) -> String {
     let mut hasher = Sha256::new();
     hasher.update(chunk);
     let hash = hasher.finalize();
-    let sha_str = base16ct::lower::encode_string(&hash);
+    base16ct::lower::encode_string(&hash)
+}
+
+// Only uses std, so its tests run everywhere
+#[cfg(any(target_os = "windows", test))]
+#[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+pub(crate) mod windows {
+    use std::{ffi::OsStr, process::Command};
+
+    /// Windows' ERROR_ELEVATION_REQUIRED
+    const ERROR_ELEVATION_REQUIRED: i32 = 740;
+
+    /// Checks if a program failed to start because it needs administrator rights
+    pub(crate) fn needs_elevation(err: &std::io::Error) -> bool {
+        err.raw_os_error() == Some(ERROR_ELEVATION_REQUIRED)
+    }
+
+    /// Runs `command` as administrator through PowerShell's `Start-Process -Verb RunAs`, which
+    /// shows the UAC prompt. Exits with the exit code of the program once it's done.
+    ///
+    /// Elevated processes don't inherit the environment, so an elevated PowerShell sets the
+    /// command's environment variables (e.g. from --env) before starting the program.
+    pub(crate) fn elevated_command(command: &Command) -> tokio::process::Command {
+        let mut inner_script = String::new();
+        for (key, value) in command.get_envs() {
+            let key = key.to_string_lossy().replace('`', "``").replace('}', "`}");
+            match value {
+                Some(value) => {
+                    inner_script.push_str(&format!("${{env:{key}}} = {}; ", quote(value)))
+                }
+                None => inner_script.push_str(&format!("${{env:{key}}} = $null; ")),
+            }
+        }
+        inner_script.push_str(&start_process(command));
+
+        let script = format!(
+            "$process = Start-Process -FilePath powershell -Verb RunAs -WindowStyle Hidden -Wait -PassThru -ArgumentList '-NoProfile','-NonInteractive','-EncodedCommand','{}'; exit $process.ExitCode",
+            encode_command(&inner_script)
+        );
+        let mut elevated = tokio::process::Command::new("powershell");
+        elevated.args(["-NoProfile", "-NonInteractive", "-Command", &script]);
+        elevated
+    }
+
+    /// A script that starts `command`'s program, waits for it and exits with its exit code
+    fn start_process(command: &Command) -> String {
+        let mut script = format!(
+            "$process = Start-Process -FilePath {} -Wait -PassThru",
+            quote(command.get_program())
+        );
+        let args: Vec<String> = command
+            .get_args()
+            .map(|arg| quote(quote_arg(&arg.to_string_lossy())))
+            .collect();
+        if !args.is_empty() {
+            script.push_str(&format!(" -ArgumentList {}", args.join(",")));
+        }
+        if let Some(current_dir) = command.get_current_dir() {
+            script.push_str(&format!(
+                " -WorkingDirectory {}",
+                quote(current_dir.as_os_str())
+            ));
+        }
+        script.push_str("; exit $process.ExitCode");
+        script
+    }
 
-    sha_str == sha
+    /// Encodes a script for PowerShell's -EncodedCommand, which takes base64 of UTF-16LE, so it
+    /// doesn't have to be quoted again
+    fn encode_command(script: &str) -> String {
+        const ALPHABET: &[u8; 64] =
+            b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let bytes: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+        for chunk in bytes.chunks(3) {
+            let n = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |n, (i, byte)| n | (*byte as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                if i <= chunk.len() {
+                    encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+                } else {
+                    encoded.push('=');
+                }
+            }
+        }
+        encoded
+    }
+
+    /// Quotes `value` as a PowerShell string literal
+    fn quote(value: impl AsRef<OsStr>) -> String {
+        format!("'{}'", value.as_ref().to_string_lossy().replace('\'', "''"))
+    }
+
+    /// Quotes `arg` for a Windows command line, so the program gets it back as one argument.
+    /// Backslashes are only special right before a quote.
+    fn quote_arg(arg: &str) -> String {
+        let mut quoted = String::from('"');
+        let mut backslashes = 0;
+        for c in arg.chars() {
+            if c == '\\' {
+                backslashes += 1;
+                continue;
+            }
+            let escapes = if c == '"' {
+                backslashes * 2 + 1
+            } else {
+                backslashes
+            };
+            quoted.push_str(&"\\".repeat(escapes));
+            quoted.push(c);
+            backslashes = 0;
+        }
+        // The closing quote mustn't be escaped by a trailing backslash
+        quoted.push_str(&"\\".repeat(backslashes * 2));
+        quoted.push('"');
+        quoted
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn encode_command_pads_like_base64() {
+            assert_eq!(encode_command(""), "");
+            assert_eq!(encode_command("abc"), "YQBiAGMA");
+            assert_eq!(encode_command("a"), "YQA=");
+            assert_eq!(encode_command("ab"), "YQBiAA==");
+        }
+
+        #[test]
+        fn encode_command_encodes_utf16_surrogate_pairs() {
+            assert_eq!(
+                encode_command("Spiel für 2 🎮"),
+                "UwBwAGkAZQBsACAAZgD8AHIAIAAyACAAPNiu3w=="
+            );
+        }
+
+        #[test]
+        fn start_process_quotes_paths_with_quotes_and_spaces() {
+            let mut command = Command::new(r"C:\Games\It's a game\game.exe");
+            command
+                .args([r#"--name "Player One""#, r"C:\Saves\"])
+                .current_dir(r"C:\Games\It's a game");
+            assert_eq!(
+                start_process(&command),
+                r#"$process = Start-Process -FilePath 'C:\Games\It''s a game\game.exe' -Wait -PassThru -ArgumentList '"--name \"Player One\""','"C:\Saves\\"' -WorkingDirectory 'C:\Games\It''s a game'; exit $process.ExitCode"#
+            );
+            assert_eq!(
+                encode_command(
+                    r"$process = Start-Process -FilePath 'C:\Games\It''s a game\game.exe' -Wait -PassThru"
+                ),
+                "JABwAHIAbwBjAGUAcwBzACAAPQAgAFMAdABhAHIAdAAtAFAAcgBvAGMAZQBzAHMAIAAtAEYAaQBsAGUAUABhAHQAaAAgACcAQwA6AFwARwBhAG0AZQBzAFwASQB0ACcAJwBzACAAYQAgAGcAYQBtAGUAXABnAGEAbQBlAC4AZQB4AGUAJwAgAC0AVwBhAGkAdAAgAC0AUABhAHMAcwBUAGgAcgB1AA=="
+            );
+        }
+    }
 }
 
 #[cfg(target_os = "macos")]
@@ -615,6 +1837,8 @@ pub(crate) mod mac {
     use async_recursion::async_recursion;
     use serde::Deserialize;
 
+    use log::trace;
+
     #[async_recursion]
     pub(crate) async fn find_app_recursive(path: &PathBuf) -> Option<PathBuf> {
         let mut subdirs = vec![];
@@ -624,7 +1848,7 @@ pub(crate) mod mac {
                 while let Ok(Some(entry)) = subpath.next_entry().await {
                     let entry_path = entry.path();
                     // Check if the current path is a .app extension
-                    println!("Checking file: {}", entry_path.display());
+                    trace!("Checking file: {}", entry_path.display());
                     if let Some(ext) = entry_path.extension() {
                         if ext == "app" {
                             return Some(entry_path);
@@ -642,7 +1866,7 @@ pub(crate) mod mac {
         }
 
         for dir in subdirs {
-            println!("Checking directory: {}", dir.display());
+            trace!("Checking directory: {}", dir.display());
             if let Some(app_path) = find_app_recursive(&dir.to_path_buf()).await {
                 return Some(app_path);
             }
@@ -651,8 +1875,15 @@ pub(crate) mod mac {
         None
     }
 
+    use crate::shared::models::BuildManifestRecord;
+
+    /// The files of a Mac build that have to be executable. Manifests don't say which files are
+    /// executables, so these are the main executable of every app bundle, from its Info.plist,
+    /// and everything else in the bundles' Contents/MacOS, like helper binaries and scripts.
     pub(crate) struct MacAppExecutables {
-        pub(crate) plist: Option<PathBuf>,
+        /// Info.plist of every app bundle. The first one is the app that's launched.
+        plists: Vec<PathBuf>,
+        executables: Vec<PathBuf>,
     }
 
     #[derive(Deserialize)]
@@ -663,51 +1894,339 @@ pub(crate) mod mac {
 
     impl MacAppExecutables {
         pub(crate) fn new() -> Self {
-            Self { plist: None }
+            Self {
+                plists: vec![],
+                executables: vec![],
+            }
         }
 
         pub(crate) fn with_plist(plist: PathBuf) -> Self {
-            Self { plist: Some(plist) }
+            Self {
+                plists: vec![plist],
+                executables: vec![],
+            }
         }
 
-        pub(crate) fn set_plist(&mut self, plist: PathBuf) {
-            self.plist = Some(plist);
+        /// Keeps track of `record` if it's an app bundle, or a file in a bundle's Contents/MacOS
+        pub(crate) fn add_record(&mut self, install_path: &Path, record: &BuildManifestRecord) {
+            let file_path = super::manifest_file_path(install_path, &record.file_name);
+            if record.is_directory() {
+                if file_path.extension().is_some_and(|ext| ext == "app") {
+                    self.plists.push(find_info_plist(&file_path));
+                }
+            } else if file_path
+                .parent()
+                .is_some_and(|parent| parent.ends_with("Contents/MacOS"))
+            {
+                self.executables.push(file_path);
+            }
         }
 
+        /// The main executable of the first app bundle, which is the one that's launched
         pub(crate) fn executable(&self) -> Option<PathBuf> {
-            match &self.plist {
-                Some(plist_path) => {
-                    let plist: BasicInfoPlist = plist::from_file(plist_path).unwrap();
-                    let executable_path = plist_path
-                        .parent()
-                        .unwrap()
-                        .join("MacOS")
-                        .join(plist.bundle_executable);
-
-                    Some(executable_path)
-                }
-                None => None,
-            }
+            bundle_executable(self.plists.first()?)
         }
 
         pub(crate) async fn mark_as_executable(&self) -> tokio::io::Result<()> {
             use std::{fs::Permissions, os::unix::prelude::PermissionsExt};
 
-            match &self.executable() {
-                Some(executable_path) => {
-                    let permissions: Permissions = PermissionsExt::from_mode(0o755); // Read/write/execute
-                    tokio::fs::set_permissions(executable_path, permissions).await?;
-                }
-                None => {
-                    println!("No executable set, cannot mark as executable.");
-                }
-            };
+            let mut executables: Vec<PathBuf> =
+                self.plists.iter().filter_map(bundle_executable).collect();
+            executables.extend(self.executables.iter().cloned());
+            executables.sort();
+            executables.dedup();
+            if executables.is_empty() {
+                println!("No executable set, cannot mark as executable.");
+            }
+
+            for executable_path in executables {
+                trace!("Marking {} as executable", executable_path.display());
+                let permissions: Permissions = PermissionsExt::from_mode(0o755); // Read/write/execute
+                tokio::fs::set_permissions(executable_path, permissions).await?;
+            }
 
             Ok(())
         }
     }
 
+    /// Reads the main executable of an app bundle from its Info.plist
+    fn bundle_executable(plist_path: &PathBuf) -> Option<PathBuf> {
+        let plist: BasicInfoPlist = match plist::from_file(plist_path) {
+            Ok(plist) => plist,
+            Err(err) => {
+                println!("Failed to read {}: {}", plist_path.display(), err);
+                return None;
+            }
+        };
+
+        Some(
+            plist_path
+                .parent()?
+                .join("MacOS")
+                .join(plist.bundle_executable),
+        )
+    }
+
     pub(crate) fn find_info_plist(app_path: &Path) -> PathBuf {
         app_path.join("Contents").join("Info.plist")
     }
 }
+
+#[cfg(target_os = "linux")]
+pub(crate) mod linux {
+    use std::{
+        fs::Permissions,
+        os::unix::prelude::PermissionsExt,
+        path::{Path, PathBuf},
+    };
+
+    use async_recursion::async_recursion;
+
+    use log::trace;
+
+    /// Collects every file under `path` that could be the game's executable: files with the
+    /// executable bit set or a common Linux binary extension. If there are none (e.g. the
+    /// permissions got lost along the way), extensionless files are used instead. Files closer to
+    /// `path` come first.
+    pub(crate) async fn find_executables_recursive(path: &PathBuf) -> Vec<PathBuf> {
+        let (executables, extensionless) = find_candidates_recursive(path).await;
+
+        if executables.is_empty() {
+            extensionless
+        } else {
+            executables
+        }
+    }
+
+    #[async_recursion]
+    async fn find_candidates_recursive(path: &PathBuf) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut subdirs = vec![];
+        let mut executables = vec![];
+        let mut extensionless = vec![];
+
+        match tokio::fs::read_dir(path).await {
+            Ok(mut subpath) => {
+                while let Ok(Some(entry)) = subpath.next_entry().await {
+                    let entry_path = entry.path();
+                    if entry_path.is_dir() {
+                        subdirs.push(entry_path);
+                        continue;
+                    }
+
+                    if !entry_path.is_file() {
+                        continue;
+                    }
+
+                    trace!("Checking file: {}", entry_path.display());
+                    let is_executable = match entry.metadata().await {
+                        Ok(metadata) => metadata.permissions().mode() & 0o111 != 0,
+                        Err(_) => false,
+                    };
+                    // Shared libraries are often marked as executable too
+                    let is_library = entry_path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.ends_with(".so") || name.contains(".so."));
+                    if is_library {
+                        continue;
+                    }
+
+                    match entry_path.extension().and_then(|ext| ext.to_str()) {
+                        Some("x86_64" | "x86" | "sh") => executables.push(entry_path),
+                        Some(_) if is_executable => executables.push(entry_path),
+                        Some(_) => {}
+                        None if is_executable => executables.push(entry_path),
+                        None => extensionless.push(entry_path),
+                    }
+                }
+            }
+            Err(err) => {
+                println!("Failed to iterate over {}: {:?}", path.display(), err);
+            }
+        }
+
+        executables.sort();
+        extensionless.sort();
+        subdirs.sort();
+        for dir in subdirs {
+            trace!("Checking directory: {}", dir.display());
+            let (mut sub_executables, mut sub_extensionless) =
+                find_candidates_recursive(&dir).await;
+            executables.append(&mut sub_executables);
+            extensionless.append(&mut sub_extensionless);
+        }
+
+        (executables, extensionless)
+    }
+
+    pub(crate) async fn mark_as_executable(executable_path: &Path) -> tokio::io::Result<()> {
+        let permissions: Permissions = PermissionsExt::from_mode(0o755); // Read/write/execute
+        tokio::fs::set_permissions(executable_path, permissions).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(manifest: &str, chunks: &str) -> (InstallPlan, Vec<BuildManifestChunksRecord>) {
+        let mut plan = InstallPlan::default();
+        for record in parse_build_manifest(manifest.as_bytes()).unwrap() {
+            plan.add_record(&record, &[]).unwrap();
+        }
+        (
+            plan,
+            parse_build_manifest_chunks(chunks.as_bytes()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn chunks_with_the_same_sha_are_queued_once_per_file() {
+        let (plan, chunk_records) = plan(
+            "Size in Bytes,Chunks,SHA,Flags,File Name\n\
+             5,1,aaa,0,one.bin\n\
+             5,1,aaa,0,dir/two.bin\n",
+            "ID,Filepath,Chunk SHA\n\
+             0,one.bin,1_same\n\
+             0,dir/two.bin,1_same\n",
+        );
+        let (queue, chunk_sha_counts) = plan.queue_chunks(chunk_records).unwrap();
+
+        let queued: Vec<_> = queue
+            .iter()
+            .map(|record| (record.file_path.as_str(), record.id))
+            .collect();
+        assert_eq!(queued, [("one.bin", 0), ("dir/two.bin", 0)]);
+        assert_eq!(chunk_sha_counts.len(), 1);
+        assert_eq!(chunk_sha_counts["1_same"], 2);
+        assert_eq!(plan.total_bytes, 10);
+    }
+
+    #[test]
+    fn files_and_chunks_listed_twice_are_only_queued_once() {
+        let (plan, chunk_records) = plan(
+            "Size in Bytes,Chunks,SHA,Flags,File Name\n\
+             5,1,aaa,0,one.bin\n\
+             5,1,aaa,0,one.bin\n",
+            "ID,Filepath,Chunk SHA\n\
+             0,one.bin,1_aaa\n\
+             0,one.bin,1_aaa\n",
+        );
+        assert_eq!(plan.files.len(), 1);
+        assert_eq!(plan.total_bytes, 5);
+
+        let (queue, chunk_sha_counts) = plan.queue_chunks(chunk_records).unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(chunk_sha_counts["1_aaa"], 1);
+    }
+
+    #[test]
+    fn a_file_listed_twice_with_different_contents_is_an_error() {
+        let mut plan = InstallPlan::default();
+        let records = parse_build_manifest(
+            "Size in Bytes,Chunks,SHA,Flags,File Name\n\
+             5,1,aaa,0,one.bin\n\
+             5,1,bbb,0,one.bin\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert!(plan.add_record(&records[0], &[]).unwrap());
+        assert!(plan.add_record(&records[1], &[]).is_err());
+    }
+
+    #[test]
+    fn empty_files_are_created_but_not_queued() {
+        let (plan, chunk_records) = plan(
+            "Size in Bytes,Chunks,SHA,Flags,File Name\n\
+             0,0,e3b0,0,empty.txt\n\
+             5,1,aaa,0,one.bin\n",
+            // Some manifests still list a chunk for empty files
+            "ID,Filepath,Chunk SHA\n\
+             0,empty.txt,1_e3b0\n\
+             0,one.bin,1_aaa\n",
+        );
+        assert_eq!(
+            plan.files,
+            [
+                ("empty.txt".to_owned(), false, None),
+                ("one.bin".to_owned(), false, Some(5))
+            ]
+        );
+        assert_eq!(plan.file_chunk_num_map["empty.txt"], 0);
+
+        let (queue, _) = plan.queue_chunks(chunk_records).unwrap();
+        assert!(queue.iter().all(|record| record.file_path != "empty.txt"));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn files_in_excluded_directories_are_excluded() {
+        let exclude = [Pattern::new("Content/Localization").unwrap()];
+        assert!(is_excluded("Content/Localization", &exclude));
+        assert!(is_excluded("Content\\Localization\\de\\text.pak", &exclude));
+        assert!(!is_excluded("Content/LocalizationTools.pak", &exclude));
+        assert!(!is_excluded("Content/main.pak", &exclude));
+    }
+
+    #[test]
+    fn delta_download_size_skips_chunks_the_old_build_has() {
+        let size = delta_download_size(
+            b"Size in Bytes,Chunks,SHA,Flags,File Name\n\
+              5,1,aaa,0,one.bin\n\
+              3,1,bbb,0,two.bin\n\
+              3,1,bbb,0,copy.bin\n",
+            b"ID,Filepath,Chunk SHA\n\
+              0,one.bin,0_aaa\n\
+              0,two.bin,1_bbb\n\
+              0,copy.bin,1_bbb\n",
+            b"ID,Filepath,Chunk SHA\n\
+              0,one.bin,4_aaa\n",
+        )
+        .unwrap();
+        assert_eq!(size, 3);
+    }
+
+    fn chunk_size_of(files: &[(u64, u16)]) -> Result<u64, CarnivalError> {
+        let manifest: String =
+            std::iter::once("Size in Bytes,Chunks,SHA,Flags,File Name\n".to_owned())
+                .chain(
+                    files
+                        .iter()
+                        .enumerate()
+                        .map(|(i, (size, chunks))| format!("{size},{chunks},aaa,0,{i}.bin\n")),
+                )
+                .collect();
+        build_chunk_size(&parse_build_manifest(manifest.as_bytes()).unwrap())
+    }
+
+    #[test]
+    fn the_last_chunk_of_a_file_is_whatever_is_left() {
+        let size = 1048576 + 5;
+        let chunk_size = chunk_size_of(&[(size, 2), (100, 1)]).unwrap();
+        assert_eq!(chunk_size, 1048576);
+        assert_eq!(expected_chunk_len(size, 0, chunk_size), 1048576);
+        assert_eq!(expected_chunk_len(size, 1, chunk_size), 5);
+    }
+
+    #[test]
+    fn builds_of_single_chunk_files_use_the_usual_chunk_size() {
+        let chunk_size = chunk_size_of(&[(5, 1), (1000, 1)]).unwrap();
+        assert_eq!(chunk_size, 1048576);
+        assert_eq!(expected_chunk_len(1000, 0, chunk_size), 1000);
+    }
+
+    #[test]
+    fn other_power_of_two_chunk_sizes_are_worked_out() {
+        let mib = 1048576;
+        let chunk_size = chunk_size_of(&[(4 * mib + 1, 2), (8 * mib + 10, 3)]).unwrap();
+        assert_eq!(chunk_size, 4 * mib);
+        assert_eq!(chunk_offset(2, chunk_size), 8 * mib);
+        assert_eq!(expected_chunk_len(8 * mib + 10, 2, chunk_size), 10);
+    }
+
+    #[test]
+    fn an_ambiguous_chunk_size_is_an_error() {
+        // Anything from 1100 to 1200 bytes fits both files, and none of those is a power of two
+        assert!(chunk_size_of(&[(2200, 2), (2401, 3)]).is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! A local, append-only log of what was done to each game. It never leaves this machine.
+
+use std::{
+    io::{BufRead, BufReader},
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::{MAX_HISTORY_SIZE, PROJECT_DIRS},
+    jsonl,
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum HistoryAction {
+    Install,
+    Update,
+    Uninstall,
+    Launch,
+}
+
+impl std::fmt::Display for HistoryAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                HistoryAction::Install => "install",
+                HistoryAction::Update => "update",
+                HistoryAction::Uninstall => "uninstall",
+                HistoryAction::Launch => "launch",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct HistoryEvent {
+    pub(crate) timestamp: DateTime<Local>,
+    pub(crate) action: HistoryAction,
+    pub(crate) slug: String,
+    pub(crate) success: bool,
+    pub(crate) details: String,
+}
+
+impl std::fmt::Display for HistoryEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} {}: {}",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            self.action,
+            self.slug,
+            if self.success { "succeeded" } else { "failed" },
+            self.details
+        )
+    }
+}
+
+fn history_path() -> PathBuf {
+    PROJECT_DIRS.cache_dir().join("history.jsonl")
+}
+
+/// Appends an event to the history. Failing to write the history never fails the command that
+/// is being recorded, so errors are only printed.
+pub(crate) fn record(action: HistoryAction, slug: &str, success: bool, details: &str) {
+    let event = HistoryEvent {
+        timestamp: Local::now(),
+        action,
+        slug: slug.to_owned(),
+        success,
+        details: details.trim().to_owned(),
+    };
+
+    if let Err(err) = jsonl::append(&history_path(), *MAX_HISTORY_SIZE, &event) {
+        println!("Failed to record history: {:?}", err);
+    }
+}
+
+/// Reads every recorded event, oldest first. Lines that can't be parsed are skipped.
+pub(crate) fn read() -> std::io::Result<Vec<HistoryEvent>> {
+    let mut events = vec![];
+    let path = history_path();
+    for path in [jsonl::rotated_path(&path), path] {
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+
+        for line in BufReader::new(file).lines() {
+            if let Ok(event) = serde_json::from_str::<HistoryEvent>(&line?) {
+                events.push(event);
+            }
+        }
+    }
+
+    Ok(events)
+}
@@ -0,0 +1,34 @@
+//! Append-only logs with one JSON value per line. Once a log grows too big it's moved aside, so
+//! only it and the log before it are kept.
+
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+/// Path a log is moved to once it grows past its maximum size, e.g. `history.jsonl.1`
+pub(crate) fn rotated_path(path: &Path) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(".1");
+    PathBuf::from(rotated)
+}
+
+/// Appends `value` to the log in `path`, after moving the log to [`rotated_path`] if it's bigger
+/// than `max_size`
+pub(crate) fn append(path: &Path, max_size: u64, value: &impl Serialize) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    if std::fs::metadata(path).is_ok_and(|m| m.len() > max_size) {
+        std::fs::rename(path, rotated_path(path))?;
+    }
+
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(line.as_bytes())
+}
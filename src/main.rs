@@ -1,48 +1,124 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
 use crate::cli::Cli;
 use crate::config::GalaConfig;
-use crate::{api::auth, config::InstalledConfig};
+use crate::{
+    api::auth,
+    config::{InstalledConfig, InstalledGames},
+};
 use api::GalaClient;
-use clap::Parser;
-use cli::Commands;
+use clap::{CommandFactory, Parser};
+use cli::{Commands, InstallOpts, LaunchOpts};
 use config::{CookieConfig, LibraryConfig, UserConfig};
-use constants::DEFAULT_BASE_INSTALL_PATH;
+use console::style;
+use constants::{DEFAULT_BASE_INSTALL_PATH, DEFAULT_VERIFY_WORKERS, PROJECT_NAME};
+use error::CarnivalError;
+use history::HistoryAction;
+use human_bytes::human_bytes;
+use indicatif::HumanDuration;
+use log::debug;
 use reqwest_cookie_store::CookieStoreMutex;
-use shared::models::api::{LoginResult, SyncResult};
+use shared::models::{
+    api::{BuildOs, LoginResult, SyncResult},
+    ChangeTag, InstallInfo,
+};
 
 mod api;
 mod cli;
 mod config;
 mod constants;
+mod error;
+mod failed_chunks;
 mod helpers;
+mod history;
+mod jsonl;
+mod output;
+mod progress;
 mod shared;
 mod utils;
+mod verify_cache;
 
 #[tokio::main]
 async fn main() {
     let args = Cli::parse();
-    let CookieConfig(cookie_store) = CookieConfig::load().expect("Failed to load cookie store");
+    args.color().apply();
+    output::set_quiet(args.quiet);
+    output::init_logger(args.verbose, args.quiet, args.color());
+    let check = args.check;
+    // Losing the cookies only means logging in again, which is better than not being able to
+    let CookieConfig(cookie_store) = CookieConfig::load_or_default();
     let cookie_store = Arc::new(CookieStoreMutex::new(cookie_store));
-    let client = reqwest::Client::with_gala(&cookie_store);
+    let client = reqwest::Client::with_gala(&cookie_store, args.client_opts());
 
+    // Moving files around is a change --check promises not to make
+    if !check && args.uses_manifests() {
+        if let Err(err) = helpers::migrate_manifests().await {
+            println!(
+                "Failed to move the stored manifests to their new location: {:?}",
+                err
+            );
+        }
+    }
+
+    if args.offline && args.needs_network() {
+        println!("This command needs an internet connection, so it can't be used with --offline.");
+        return;
+    }
+
+    // Whether the saved library is up to date, and whether the saved session stopped working
+    let mut synced = false;
+    let mut session_expired = false;
+    // Sync messages go to stderr, so they don't end up in the output of commands meant for
+    // scripts, like `info --latest`
     if args.needs_sync() {
-        println!("Syncing library...");
+        if !output::is_quiet() {
+            eprintln!("Syncing library...");
+        }
         match api::auth::sync(&client).await {
-            Ok(Some(result)) => save_user_info(&result),
+            Ok(Some(result)) => {
+                save_user_info(&result);
+                synced = true;
+            }
+            Ok(None) if matches!(args.command, Commands::Status) => session_expired = true,
             Ok(None) => {
-                println!("Failed to sync: your authentication is invalid.");
-                return;
+                eprintln!("Your session has expired. Run `login` again.");
+                // Let scripts tell this apart from other failures
+                std::process::exit(1);
+            }
+            Err(err) if args.works_offline() => {
+                eprintln!("Failed to sync, using the saved library instead: {err}");
             }
             Err(err) => {
-                println!("Failed to sync: {err:#?}");
+                eprintln!("Failed to sync: {err:#?}");
+                // `list-updates --quiet` only reports through its exit code
+                if output::is_quiet() && matches!(args.command, Commands::ListUpdates { .. }) {
+                    std::process::exit(1);
+                }
                 return;
             }
         };
     }
 
+    if !args.mirrors().is_empty() {
+        api::product::use_fastest_mirror(&client, args.mirrors()).await;
+    }
+
     match args.command {
-        Commands::Login { email, password } => {
+        Commands::Login {
+            email: Some(email),
+            password,
+            ..
+        } => {
+            if check {
+                println!("Would log in as {email}");
+                return;
+            }
+
             let password = match password {
                 Some(password) => password,
                 None => {
@@ -71,117 +147,648 @@ async fn main() {
                 Err(err) => println!("Failed to login: {err:#?}"),
             }
         }
+        Commands::Login {
+            token, cookie_file, ..
+        } => {
+            if check {
+                println!("Would import your session cookies");
+                return;
+            }
+
+            let imported = match (token, cookie_file) {
+                (Some(token), _) => auth::import_session_token(&cookie_store, &token),
+                (None, Some(path)) => match auth::import_cookie_file(&cookie_store, &path) {
+                    Ok(imported) => imported,
+                    Err(err) => {
+                        println!("Failed to read {}: {:?}", path.display(), err);
+                        return;
+                    }
+                },
+                (None, None) => 0,
+            };
+            if imported == 0 {
+                println!("Couldn't find any IndieGala cookies to import");
+                return;
+            }
+
+            match auth::sync(&client).await {
+                Ok(Some(result)) => {
+                    save_user_info(&result);
+                    println!("Imported {imported} cookies");
+                }
+                Ok(None) => {
+                    println!("Failed to sync: the imported session is invalid or has expired.");
+                }
+                Err(err) => println!("Failed to sync: {err:#?}"),
+            };
+        }
         Commands::Logout => {
+            if check {
+                println!("Would clear your user info, library and cookies");
+                return;
+            }
+
             UserConfig::clear().expect("Error clearing user config");
             LibraryConfig::clear().expect("Error clearing library");
             cookie_store.lock().unwrap().clear();
         }
-        Commands::Library => {
-            let library = LibraryConfig::load().expect("Failed to load library");
-            for product in library.collection {
-                println!("{}", product);
+        Commands::Sync => {
+            if check {
+                println!("Would sync your library");
+                return;
+            }
+
+            let old_library = LibraryConfig::load_or_default();
+            match api::auth::sync(&client).await {
+                Ok(Some(result)) => {
+                    save_user_info(&result);
+                    let collection = &result.library_config.collection;
+                    let new_games = collection
+                        .iter()
+                        .filter(|product| {
+                            !old_library
+                                .collection
+                                .iter()
+                                .any(|old| old.slugged_name == product.slugged_name)
+                        })
+                        .count();
+                    println!(
+                        "Synced your library: {} games, {} new",
+                        collection.len(),
+                        new_games
+                    );
+                }
+                Ok(None) => {
+                    println!("Your session has expired. Run `login` again.");
+                    std::process::exit(1);
+                }
+                Err(err) => {
+                    println!("Failed to sync: {err:#?}");
+                }
+            }
+        }
+        Commands::Library { detailed, sizes } => {
+            let library = LibraryConfig::load_or_default();
+            if !detailed {
+                for product in library.collection {
+                    println!("{}", product);
+                }
+                return;
+            }
+
+            let installed = InstalledConfig::load_or_default();
+            let sizes = if sizes {
+                utils::latest_build_sizes(&client, &library.collection).await
+            } else {
+                vec![]
+            };
+            let mut rows = vec![];
+            for (i, product) in library.collection.iter().enumerate() {
+                let latest_version = product.get_latest_version(None);
+                let installed_versions = match installed.get(&product.slugged_name) {
+                    Some(game) => game
+                        .iter()
+                        .map(|info| format!("{} ({})", info.version, info.os))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    None => "-".to_owned(),
+                };
+                let mut row = vec![
+                    product.slugged_name.clone(),
+                    latest_version.map_or("-".to_owned(), |v| v.version.clone()),
+                    latest_version.map_or("-".to_owned(), |v| v.os.to_string()),
+                    installed_versions,
+                ];
+                if let Some(size) = sizes.get(i) {
+                    row.push(size.map_or("?".to_owned(), human_bytes));
+                }
+                row.push(product.name.clone());
+                rows.push(row);
+            }
+
+            let mut header = vec!["SLUG", "LATEST", "PLATFORM", "INSTALLED"];
+            if !sizes.is_empty() {
+                header.push("SIZE");
+            }
+            header.push("NAME");
+            output::print_table(&header, &rows);
+        }
+        Commands::Status => {
+            let user_config = UserConfig::load_or_default();
+            let library = LibraryConfig::load_or_default();
+            let installed = InstalledConfig::load_or_default();
+
+            match &user_config.user_info {
+                _ if session_expired => {
+                    println!("Logged in as: nobody, your session has expired. Run `login` again.")
+                }
+                Some(user_info) if user_info.user_found == "true" => println!(
+                    "Logged in as: {}",
+                    user_info
+                        .username
+                        .as_ref()
+                        .or(user_info.email.as_ref())
+                        .map_or("unknown user", |name| name.as_str())
+                ),
+                _ => println!("Logged in as: nobody, use `login` to log in"),
+            }
+            println!("Library: {} games", library.collection.len());
+            let installs = installed.installs();
+            println!(
+                "Installed: {} games ({} installs)",
+                installed.len(),
+                installs.len()
+            );
+
+            let mut disk_used = 0;
+            // DLC shares its game's directory, which is only counted once
+            let mut counted = HashSet::new();
+            for (slug, install_info) in &installs {
+                if !counted.insert(&install_info.install_path) {
+                    continue;
+                }
+                match helpers::dir_size(&install_info.install_path).await {
+                    Ok(size) => disk_used += size,
+                    Err(err) => debug!(
+                        "Failed to get the size of {slug} ({}): {:?}",
+                        install_info.os, err
+                    ),
+                }
+            }
+            println!("Disk used: {}", human_bytes(disk_used as f64));
+
+            // The library is only up to date when it was just synced
+            if args.offline {
+                println!("Updates: unknown while offline");
+                return;
+            }
+            if !synced {
+                println!("Updates: unknown, the library wasn't synced");
+                return;
+            }
+            match utils::check_updates(&library, &installed).await {
+                Ok(available_updates) if available_updates.is_empty() => {
+                    println!("Updates: everything is up to date")
+                }
+                Ok(available_updates) => println!(
+                    "Updates: {} available, see `list-updates`",
+                    style(available_updates.len()).yellow()
+                ),
+                Err(err) => println!("Updates: failed to check ({:?})", err),
+            }
+        }
+        Commands::Installed => {
+            let installed = InstalledConfig::load_or_default();
+            let library = LibraryConfig::load_or_default();
+            if installed.is_empty() {
+                println!("No games installed");
+                return;
+            }
+
+            for (slug, install_info) in installed.installs() {
+                let latest_version = library
+                    .collection
+                    .iter()
+                    .find(|p| &p.slugged_name == slug)
+                    .and_then(|p| p.get_latest_version(Some(&install_info.os)));
+                let update = match latest_version {
+                    Some(latest) if latest.version != install_info.version => format!(
+                        " {}",
+                        style(format!("(update available: {})", latest.version)).yellow()
+                    ),
+                    _ => String::new(),
+                };
+                let play_time = match install_info.play_time_summary() {
+                    Some(summary) => format!(", {summary}"),
+                    None => String::new(),
+                };
+                println!(
+                    "{slug} {} ({}) in {}{play_time}{update}",
+                    install_info.version,
+                    install_info.os,
+                    install_info.install_path.display()
+                );
             }
         }
         Commands::Install {
-            slug,
+            slugs,
+            from_file,
             version,
             path,
             base_path,
+            install_prefix,
             os,
+            platform_preference,
+            keep_partial,
+            reinstall,
+            manifest_only,
+            with_dlc,
             install_opts,
         } => {
-            let mut installed = InstalledConfig::load().expect("Failed to load installed");
-            if installed.contains_key(&slug) && !install_opts.info {
-                println!("{slug} already installed.");
+            let install_opts = InstallOpts {
+                check,
+                manifest_only,
+                ..install_opts
+            };
+            // Nothing is written to the install directory when only the manifests are wanted
+            let skip_install_checks = install_opts.info || manifest_only;
+            let mut games: Vec<(String, Option<String>)> = slugs
+                .into_iter()
+                .map(|slug| (slug, version.clone()))
+                .collect();
+            if let Some(from_file) = &from_file {
+                match utils::read_slug_list(from_file).await {
+                    Ok(listed) => games.extend(listed),
+                    Err(err) => {
+                        println!("Failed to read {}: {}", from_file.display(), err);
+                        return;
+                    }
+                }
+            }
+            if games.len() > 1 && (path.is_some() || version.is_some()) {
+                println!("--path and --version can only be used when installing a single game");
                 return;
             }
 
-            let install_path = match (path, base_path) {
-                (Some(path), _) => path,
-                (None, Some(base_path)) => base_path.join(&slug),
-                (None, None) => DEFAULT_BASE_INSTALL_PATH.join(&slug),
+            let os_preference = match &os {
+                Some(os) => vec![os.clone()],
+                None if !platform_preference.is_empty() => platform_preference,
+                None => BuildOs::preferred(),
             };
+            let mut installed = InstalledConfig::load_or_default();
+            let library = LibraryConfig::load_or_default();
+            // Games that match several others, and the user didn't pick one of, are skipped
+            let mut unresolved = vec![];
+            games.retain_mut(
+                |(slug, _)| match utils::resolve_slug(slug, &library, None) {
+                    Ok(resolved) => {
+                        *slug = resolved;
+                        true
+                    }
+                    Err(err) => {
+                        println!("{err}");
+                        unresolved.push(slug.clone());
+                        false
+                    }
+                },
+            );
+            // Each game's DLC is installed right after it, into its directory
+            let mut dlc_of: HashMap<String, String> = HashMap::new();
+            if with_dlc {
+                let mut with_dlcs = vec![];
+                for (slug, version) in games {
+                    let dlcs = match library.collection.iter().find(|p| p.slugged_name == slug) {
+                        Some(product) => utils::find_dlcs(product, &library),
+                        None => vec![],
+                    };
+                    with_dlcs.push((slug.clone(), version));
+                    for dlc in dlcs {
+                        println!("Found DLC {dlc} for {slug}");
+                        dlc_of.insert(dlc.slugged_name.clone(), slug.clone());
+                        with_dlcs.push((dlc.slugged_name.clone(), None));
+                    }
+                }
+                // DLC that was also listed on its own is only installed once
+                let mut seen = HashSet::new();
+                with_dlcs.retain(|(slug, _)| seen.insert(slug.clone()));
+                games = with_dlcs;
+            }
 
-            let library = LibraryConfig::load().expect("Failed to load library");
-            let selected_version = match (
-                version,
-                library.collection.iter().find(|p| p.slugged_name == slug),
-            ) {
-                (Some(version), Some(product)) => {
-                    match product.version.iter().find(|v| {
-                        v.version == version
-                            && match &os {
-                                Some(target) => v.os == *target,
-                                None => true,
+            let mut succeeded = vec![];
+            let mut skipped: Vec<&String> = unresolved.iter().collect();
+            let mut failed = vec![];
+
+            for (slug, version) in &games {
+                // DLC goes in the directory of its game, and is for the same OS
+                let base_install = match dlc_of.get(slug) {
+                    Some(base) => match installed.get_install(base, os.as_ref()) {
+                        Ok(info) => Some(info.clone()),
+                        // Either it isn't installed, or it's installed for more than one OS and
+                        // needs --os
+                        Err(err) => {
+                            println!("{err} Skipping its DLC {slug}...");
+                            skipped.push(slug);
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+                let installed_game = installed.get(slug);
+                // Reinstalls replace the existing install in place
+                let existing_install = match reinstall {
+                    true => installed.get_install(slug, os.as_ref()).ok().cloned(),
+                    false => None,
+                };
+                if !skip_install_checks && existing_install.is_none() {
+                    match (&os, installed_game) {
+                        (Some(os), Some(game)) if game.iter().any(|info| &info.os == os) => {
+                            println!("{slug} already installed for {os}. Skipping...");
+                            skipped.push(slug);
+                            continue;
+                        }
+                        (None, Some(_)) => {
+                            println!(
+                                "{slug} already installed. Use --os to install it for another OS. Skipping..."
+                            );
+                            skipped.push(slug);
+                            continue;
+                        }
+                        _ => {}
+                    }
+                }
+
+                let product = match library.collection.iter().find(|p| &p.slugged_name == slug) {
+                    Some(product) => product,
+                    None => {
+                        println!("{slug} is not in your library. Skipping...");
+                        skipped.push(slug);
+                        continue;
+                    }
+                };
+
+                let dir_name = match install_prefix.as_ref().map(|prefix| prefix.expand(product)) {
+                    Some(Ok(dir_name)) => dir_name,
+                    Some(Err(err)) => {
+                        println!("{err}. Skipping {slug}...");
+                        failed.push(slug);
+                        continue;
+                    }
+                    None => PathBuf::from(slug),
+                };
+                // Keep installs for different OSes apart
+                let dir_name = match (&os, installed_game) {
+                    (Some(os), Some(_)) => {
+                        let mut dir_name = dir_name.into_os_string();
+                        dir_name.push(format!("-{os}"));
+                        PathBuf::from(dir_name)
+                    }
+                    _ => dir_name,
+                };
+                let install_path = match (&existing_install, &path, &base_path) {
+                    (Some(existing), _, _) => existing.install_path.to_owned(),
+                    (None, Some(path), _) => path.to_owned(),
+                    (None, None, Some(base_path)) => base_path.join(dir_name),
+                    (None, None, None) => DEFAULT_BASE_INSTALL_PATH.join(dir_name),
+                };
+                let install_path = match (&existing_install, &base_install) {
+                    (None, Some(base)) => base.install_path.to_owned(),
+                    _ => install_path,
+                };
+
+                let selected_version = match &version {
+                    Some(version) => {
+                        match product.version.iter().find(|v| {
+                            &v.version == version
+                                && match &os {
+                                    Some(target) => v.os == *target,
+                                    None => true,
+                                }
+                        }) {
+                            Some(version) => Some(version),
+                            None => {
+                                println!("Can't find or install build {version} for {slug}");
+                                failed.push(slug);
+                                continue;
                             }
-                    }) {
-                        Some(version) => Some(version),
-                        None => {
-                            println!("Can't find or install build {version} for {slug}");
-                            return;
+                        }
+                    }
+                    None => None,
+                };
+
+                if !skip_install_checks {
+                    let untracked = existing_install.is_none()
+                        && !installed
+                            .installs()
+                            .iter()
+                            .any(|(_, info)| info.install_path == install_path)
+                        && std::fs::read_dir(&install_path)
+                            .is_ok_and(|mut entries| entries.next().is_some());
+                    let overwrite = match &existing_install {
+                        Some(_) => true,
+                        None if untracked => {
+                            let message = format!(
+                                "{} already exists, but isn't a tracked install.",
+                                install_path.display()
+                            );
+                            if check {
+                                println!("{message}");
+                                true
+                            } else if reinstall
+                                || output::confirm(&format!("{message} Overwrite it?"))
+                            {
+                                true
+                            } else {
+                                println!("Pass --reinstall to overwrite it. Skipping {slug}...");
+                                skipped.push(slug);
+                                continue;
+                            }
+                        }
+                        None => false,
+                    };
+
+                    let sharing = utils::installs_sharing(&installed, &install_path, slug);
+                    if overwrite && !sharing.is_empty() {
+                        // Removing it would remove the other installs too
+                        println!(
+                            "Keeping {}, since {} installed in it too. Its files are overwritten instead.",
+                            install_path.display(),
+                            sharing_list(&sharing)
+                        );
+                    } else if overwrite && check {
+                        println!("Would remove {}", install_path.display());
+                    } else if overwrite {
+                        println!("Removing {}...", install_path.display());
+                        if let Err(err) = utils::uninstall(&install_path).await {
+                            println!("Failed to remove {}: {:?}", install_path.display(), err);
+                            failed.push(slug);
+                            continue;
+                        }
+                        // Don't keep pointing at the removed files if the install fails
+                        if let Some(existing) = &existing_install {
+                            installed.remove_install(slug, Some(&existing.os)).ok();
+                            installed
+                                .store()
+                                .expect("Failed to update installed config");
                         }
                     }
                 }
-                (_, None) => {
-                    println!("{slug} is not in your library");
+
+                // Reinstalls keep the OS of the existing install
+                let os_preference = match (&existing_install, &base_install) {
+                    (Some(existing), _) => vec![existing.os.clone()],
+                    (None, Some(base)) => vec![base.os.clone()],
+                    (None, None) => os_preference.clone(),
+                };
+                let (progress, bars) = output::download_bars();
+                let result = utils::install(
+                    client.clone(),
+                    slug,
+                    &install_path,
+                    install_opts.clone(),
+                    selected_version,
+                    &os_preference,
+                    keep_partial,
+                    Some(progress),
+                )
+                .await;
+                bars.await.ok();
+                match result {
+                    Ok(Ok((info, Some(install_info)))) => {
+                        println!("{}", info);
+                        let verified =
+                            !install_opts.verify || verify_install(slug, &install_info).await;
+                        if verified {
+                            history::record(HistoryAction::Install, slug, true, &info);
+                        } else {
+                            history::record(
+                                HistoryAction::Install,
+                                slug,
+                                false,
+                                "Installed, but failed verification",
+                            );
+                        }
+                        installed.insert_install(slug.to_owned(), install_info);
+                        installed
+                            .store()
+                            .expect("Failed to update installed config");
+                        if verified {
+                            succeeded.push(slug);
+                        } else {
+                            failed.push(slug);
+                        }
+                    }
+                    Ok(Ok((info, None))) => {
+                        println!("{}", info);
+                        succeeded.push(slug);
+                    }
+                    Ok(Err(err)) => {
+                        println!("Failed to install {}: {}", slug, err);
+                        history::record(HistoryAction::Install, slug, false, &err);
+                        failed.push(slug);
+                    }
+                    Err(CarnivalError::Cancelled) => {
+                        println!("Skipping {slug}...");
+                        skipped.push(slug);
+                    }
+                    Err(err) => {
+                        println!("Failed to install {}: {}", slug, err);
+                        history::record(HistoryAction::Install, slug, false, &err.to_string());
+                        failed.push(slug);
+                    }
+                };
+            }
+
+            if games.len() + unresolved.len() > 1 {
+                output::print_summary(
+                    "Install summary",
+                    &[
+                        (style("Succeeded").green(), &succeeded),
+                        (style("Skipped").yellow(), &skipped),
+                        (style("Failed").red(), &failed),
+                    ],
+                );
+            }
+        }
+        Commands::Uninstall { slug, keep, os } => {
+            let mut installed = InstalledConfig::load_or_default();
+            let library = LibraryConfig::load_or_default();
+            let query = slug;
+            let slug = match utils::resolve_slug(&query, &library, Some(&installed)) {
+                Ok(slug) => slug,
+                Err(err) => {
+                    println!("{err}");
                     return;
                 }
-                _ => None,
             };
-            match utils::install(
-                client.clone(),
-                &slug,
-                &install_path,
-                install_opts,
-                selected_version,
-                os,
-            )
-            .await
-            {
-                Ok(Ok((info, Some(install_info)))) => {
-                    println!("{}", info);
+            // Deleting the wrong game can't be undone, so a partial match has to be confirmed
+            if slug != query && !check && !output::confirm(&format!("Uninstall {slug}?")) {
+                println!("Not uninstalling {slug}. Pass its full slug to skip this question.");
+                return;
+            }
 
-                    installed.insert(slug, install_info);
-                    installed
-                        .store()
-                        .expect("Failed to update installed config");
-                }
-                Ok(Ok((info, None))) => {
-                    println!("{}", info);
-                }
-                Ok(Err(err)) => {
-                    println!("Failed to install {}: {:?}", &slug, err);
-                }
+            let install_info = match installed.remove_install(&slug, os.as_ref()) {
+                Ok(info) => info,
                 Err(err) => {
-                    println!("Failed to install {}: {:?}", &slug, err);
-                }
-            };
-        }
-        Commands::Uninstall { slug, keep } => {
-            let mut installed = InstalledConfig::load().expect("Failed to load installed");
-            let install_info = match installed.remove(&slug) {
-                Some(info) => info,
-                None => {
-                    println!("{slug} is not installed.");
+                    println!("{err}");
                     return;
                 }
             };
 
-            let folder_removed = if keep {
-                false
-            } else {
-                match utils::uninstall(&install_info.install_path).await {
-                    Ok(()) => true,
-                    Err(err) => {
-                        println!("Failed to uninstall {slug}: {:?}", err);
-                        false
+            // Only the game's own files are removed while other installs use the directory, like
+            // a game's DLC
+            let sharing = utils::installs_sharing(&installed, &install_info.install_path, &slug);
+            if !keep && !sharing.is_empty() {
+                println!(
+                    "{} {slug}'s files from {}, since {} installed in it too.",
+                    if check {
+                        "Would only remove"
+                    } else {
+                        "Only removing"
+                    },
+                    install_info.install_path.display(),
+                    sharing_list(&sharing)
+                );
+            }
+
+            if check {
+                if !keep {
+                    if sharing.is_empty() {
+                        println!("Would remove {}", install_info.install_path.display());
+                    }
+                    if let Ok((removed, removed_bytes)) =
+                        helpers::prune_build_manifests(&installed, Some(&slug), true).await
+                    {
+                        if removed > 0 {
+                            println!(
+                                "Would remove {removed} stored manifests ({})",
+                                human_bytes(removed_bytes as f64)
+                            );
+                        }
                     }
                 }
+                println!("Would remove {slug} from installed games");
+                return;
+            }
+
+            let removed = match (keep, sharing.is_empty()) {
+                (true, _) => Ok(false),
+                (false, true) => utils::uninstall(&install_info.install_path)
+                    .await
+                    .map(|()| true),
+                (false, false) => utils::uninstall_files(&slug, &install_info, &installed)
+                    .await
+                    .map(|()| false),
             };
+            let folder_removed = match removed {
+                Ok(folder_removed) => folder_removed,
+                Err(err) if sharing.is_empty() => {
+                    println!("Failed to uninstall {slug}: {:?}", err);
+                    false
+                }
+                Err(err) => {
+                    // Nothing's forgotten, since the files couldn't be told apart anymore
+                    println!("Failed to uninstall {slug}: {:?}", err);
+                    return;
+                }
+            };
+            let succeeded = folder_removed || keep || !sharing.is_empty();
             installed
                 .store()
                 .expect("Failed to update installed config");
-            println!(
+            // The manifests of this build aren't needed anymore, unless it's still installed for
+            // another OS or the files were kept
+            if !keep {
+                match helpers::prune_build_manifests(&installed, Some(&slug), false).await {
+                    Ok((0, _)) => {}
+                    Ok((removed, removed_bytes)) => println!(
+                        "Removed {removed} stored manifests ({})",
+                        human_bytes(removed_bytes as f64)
+                    ),
+                    Err(err) => println!("Failed to remove the manifests of {slug}: {:?}", err),
+                }
+            }
+
+            let message = format!(
                 "{slug} uninstalled successfuly. {} was {}.",
                 install_info.install_path.display(),
                 if folder_removed {
@@ -190,48 +797,389 @@ async fn main() {
                     "not removed"
                 }
             );
+            println!("{message}");
+            history::record(HistoryAction::Uninstall, &slug, succeeded, &message);
+        }
+        Commands::Move { slug, new_path, os } => {
+            let mut installed = InstalledConfig::load_or_default();
+            let install_info = match installed.get_install_mut(&slug, os.as_ref()) {
+                Ok(info) => info,
+                Err(err) => {
+                    println!("{err}");
+                    return;
+                }
+            };
+
+            if check {
+                println!(
+                    "Would move {slug} from {} to {}",
+                    install_info.install_path.display(),
+                    new_path.display()
+                );
+                return;
+            }
+
+            println!(
+                "Moving {slug} from {} to {}...",
+                install_info.install_path.display(),
+                new_path.display()
+            );
+            let old_path = install_info.install_path.clone();
+            match utils::move_install(&old_path, &new_path).await {
+                Ok(()) => {
+                    install_info.install_path = new_path.clone();
+                    // Installs in the same directory, like DLC, moved along with it
+                    let sharing: Vec<(String, BuildOs)> = installed
+                        .installs()
+                        .into_iter()
+                        .filter(|(_, info)| info.install_path == old_path)
+                        .map(|(slug, info)| (slug.to_owned(), info.os.clone()))
+                        .collect();
+                    for (other, os) in sharing {
+                        if let Ok(info) = installed.get_install_mut(&other, Some(&os)) {
+                            info.install_path = new_path.clone();
+                            println!("Moved {other} along with it");
+                        }
+                    }
+                    installed
+                        .store()
+                        .expect("Failed to update installed config");
+                    println!("{slug} moved successfully.");
+                }
+                Err(err) => {
+                    println!("Failed to move {slug}: {}", err);
+                }
+            };
+        }
+        Commands::Adopt {
+            slug,
+            path,
+            version,
+        } => {
+            let mut installed = InstalledConfig::load_or_default();
+            if !path.is_dir() {
+                println!("{} is not a directory.", path.display());
+                return;
+            }
+
+            let library = LibraryConfig::load_or_default();
+            let product = match library.collection.iter().find(|p| p.slugged_name == slug) {
+                Some(product) => product,
+                None => {
+                    println!("{slug} is not in your library");
+                    return;
+                }
+            };
+            let version = match version {
+                Some(version) => match product.version.iter().find(|v| v.version == version) {
+                    Some(version) => Some(version),
+                    None => {
+                        println!("Couldn't find build {version} for {slug}");
+                        return;
+                    }
+                },
+                None => None,
+            };
+
+            println!("Identifying the build installed in {}...", path.display());
+            match utils::identify_installed_version(&client, product, &path, version, check).await {
+                Ok(Some(info)) => {
+                    if let Ok(existing) = installed.get_install(&slug, Some(&info.os)) {
+                        println!(
+                            "{slug} is already installed for {} in {}.",
+                            info.os,
+                            existing.install_path.display()
+                        );
+                        return;
+                    }
+                    if check {
+                        println!("Would adopt build {} of {slug}.", info.version);
+                        return;
+                    }
+
+                    println!("Found build {} of {slug}.", info.version);
+                    installed.insert_install(slug.to_owned(), info);
+                    installed
+                        .store()
+                        .expect("Failed to update installed config");
+                    println!("{slug} is now managed.");
+                }
+                Ok(None) => {
+                    println!(
+                        "Couldn't match the files in {} to {}.",
+                        path.display(),
+                        match version {
+                            Some(version) => format!("build {} of {slug}", version.version),
+                            None => format!("any build of {slug}"),
+                        }
+                    );
+                }
+                Err(err) => {
+                    println!("Failed to identify installed build: {:?}", err);
+                }
+            }
         }
-        Commands::ListUpdates => {
-            let installed = InstalledConfig::load().expect("Failed to load installed");
-            let library = LibraryConfig::load().expect("Failed to load library");
+        Commands::ListUpdates { since } => {
+            let installed = InstalledConfig::load_or_default();
+            let library = LibraryConfig::load_or_default();
 
-            match utils::check_updates(library, installed).await {
-                Ok(available_updates) => {
+            match utils::check_updates(&library, &installed).await {
+                Ok(mut available_updates) => {
+                    if let Some(since) = since {
+                        available_updates
+                            .retain(|(_, _, latest_version)| latest_version.date >= since);
+                    }
+                    if output::is_quiet() {
+                        // Same as `dnf check-update`, so scripts can tell it apart from a failure
+                        if !available_updates.is_empty() {
+                            std::process::exit(100);
+                        }
+                        return;
+                    }
                     if available_updates.is_empty() {
-                        println!("No available updates");
+                        match since {
+                            Some(since) => {
+                                println!("No updates since {}", since.format("%Y-%m-%d %H:%M"))
+                            }
+                            None => println!("No available updates"),
+                        }
                         return;
                     }
 
-                    for (slug, latest_version) in available_updates {
-                        println!("{slug} has an update -> {latest_version}");
+                    for (slug, os, latest_version) in available_updates {
+                        println!("{slug} ({os}) has an update -> {}", latest_version.version);
                     }
                 }
                 Err(err) => {
                     println!("Failed to check for updates: {:?}", err);
+                    if output::is_quiet() {
+                        std::process::exit(1);
+                    }
                 }
             };
         }
+        Commands::UpdateAll { install_opts } => {
+            let install_opts = InstallOpts {
+                check,
+                ..install_opts
+            };
+            let mut installed = InstalledConfig::load_or_default();
+            let library = LibraryConfig::load_or_default();
+
+            let available_updates = match utils::check_updates(&library, &installed).await {
+                Ok(available_updates) => available_updates,
+                Err(err) => {
+                    println!("Failed to check for updates: {:?}", err);
+                    return;
+                }
+            };
+            if available_updates.is_empty() {
+                println!("No available updates");
+                return;
+            }
+
+            let mut succeeded = vec![];
+            let mut skipped = vec![];
+            let mut failed = vec![];
+
+            for (slug, os, latest_version) in &available_updates {
+                let install_info = match installed.get_install(slug, Some(os)) {
+                    Ok(info) => info,
+                    Err(_) => continue,
+                };
+
+                println!("Updating {slug} ({os}) -> {}...", latest_version.version);
+                let (progress, bars) = output::download_bars();
+                let result = utils::update(
+                    client.clone(),
+                    &library,
+                    slug,
+                    install_opts.clone(),
+                    install_info,
+                    None,
+                    Some(progress),
+                )
+                .await;
+                bars.await.ok();
+                match result {
+                    Ok((info, Some(install_info))) => {
+                        println!("{}", info);
+                        let verified =
+                            !install_opts.verify || verify_install(slug, &install_info).await;
+                        if verified {
+                            history::record(HistoryAction::Update, slug, true, &info);
+                        } else {
+                            history::record(
+                                HistoryAction::Update,
+                                slug,
+                                false,
+                                "Updated, but failed verification",
+                            );
+                        }
+                        installed.insert_install(slug.to_owned(), install_info);
+                        installed
+                            .store()
+                            .expect("Failed to update installed config");
+                        if verified {
+                            succeeded.push(slug);
+                        } else {
+                            failed.push(slug);
+                        }
+                    }
+                    Ok((info, None)) => {
+                        println!("{}", info);
+                        if !install_opts.info && !install_opts.check {
+                            history::record(HistoryAction::Update, slug, false, &info);
+                            failed.push(slug);
+                        }
+                    }
+                    Err(CarnivalError::Cancelled) => {
+                        println!("Skipping {slug}...");
+                        skipped.push(slug);
+                    }
+                    Err(err) => {
+                        println!("Failed to update {slug}: {}", err);
+                        history::record(HistoryAction::Update, slug, false, &err.to_string());
+                        failed.push(slug);
+                    }
+                };
+            }
+
+            output::print_summary(
+                "Update summary",
+                &[
+                    (style("Updated").green(), &succeeded),
+                    (style("Skipped").yellow(), &skipped),
+                    (style("Failed").red(), &failed),
+                ],
+            );
+        }
         Commands::Update {
             slug,
             version,
+            path,
+            os,
+            allow_downgrade,
             install_opts,
         } => {
-            let mut installed = InstalledConfig::load().expect("Failed to load installed");
-            let install_info = match installed.remove(&slug) {
-                Some(info) => info,
-                None => {
-                    println!("{slug} is not installed.");
+            let install_opts = InstallOpts {
+                check,
+                ..install_opts
+            };
+            let mut installed = InstalledConfig::load_or_default();
+            let library = LibraryConfig::load_or_default();
+            // Games that aren't installed yet can be adopted with --path
+            let slug =
+                match utils::resolve_slug(&slug, &library, path.is_none().then_some(&installed)) {
+                    Ok(slug) => slug,
+                    Err(err) => {
+                        println!("{err}");
+                        return;
+                    }
+                };
+            let install_info = match (installed.get_install(&slug, os.as_ref()), path) {
+                (Ok(info), None) => info.clone(),
+                (Ok(info), Some(path)) => {
+                    if path != info.install_path {
+                        println!(
+                            "{slug} is already installed in {}.",
+                            info.install_path.display()
+                        );
+                        return;
+                    }
+                    info.clone()
+                }
+                (Err(_), Some(path)) => {
+                    let product = match library.collection.iter().find(|p| p.slugged_name == slug) {
+                        Some(product) => product,
+                        None => {
+                            println!("{slug} is not in your library");
+                            return;
+                        }
+                    };
+
+                    println!("Identifying the build installed in {}...", path.display());
+                    match utils::identify_installed_version(&client, product, &path, None, check)
+                        .await
+                    {
+                        Ok(Some(info)) => {
+                            if let Ok(existing) = installed.get_install(&slug, Some(&info.os)) {
+                                println!(
+                                    "{slug} is already installed for {} in {}.",
+                                    info.os,
+                                    existing.install_path.display()
+                                );
+                                return;
+                            }
+
+                            println!("Found build {} of {slug}.", info.version);
+                            if check {
+                                println!(
+                                    "Would adopt {slug} ({}) in {}",
+                                    info.os,
+                                    info.install_path.display()
+                                );
+                                info
+                            } else {
+                                // Track the adopted install right away, so it stays managed even
+                                // if the update itself fails.
+                                installed.insert_install(slug.to_owned(), info.clone());
+                                installed
+                                    .store()
+                                    .expect("Failed to update installed config");
+                                info
+                            }
+                        }
+                        Ok(None) => {
+                            println!(
+                                "Couldn't match the files in {} to any build of {slug}.",
+                                path.display()
+                            );
+                            return;
+                        }
+                        Err(err) => {
+                            println!("Failed to identify installed build: {:?}", err);
+                            return;
+                        }
+                    }
+                }
+                (Err(err), None) => {
+                    println!("{err}");
                     return;
                 }
             };
-            let library = LibraryConfig::load().expect("Failed to load library");
             let selected_version = match (
                 version,
                 library.collection.iter().find(|p| p.slugged_name == slug),
             ) {
                 (Some(version), Some(product)) => {
                     match product.version.iter().find(|v| v.version == version) {
-                        Some(version) => Some(version),
+                        Some(version) => {
+                            let installed_version = product.version.iter().find(|v| {
+                                v.version == install_info.version && v.os == install_info.os
+                            });
+                            let is_downgrade = installed_version
+                                .is_some_and(|installed| version.date < installed.date);
+                            if is_downgrade && check {
+                                println!(
+                                    "Would downgrade {slug} from build {} to build {}",
+                                    install_info.version, version.version
+                                );
+                            } else if is_downgrade
+                                && !allow_downgrade
+                                && !output::confirm(&format!(
+                                    "Build {} is older than the installed build {}. Downgrade {slug}?",
+                                    version.version, install_info.version
+                                ))
+                            {
+                                println!(
+                                    "Not downgrading {slug} to build {}. Pass --allow-downgrade to downgrade anyway.",
+                                    version.version
+                                );
+                                return;
+                            }
+                            Some(version)
+                        }
                         None => {
                             println!("Couldn't find build {version} for {slug}");
                             return;
@@ -245,130 +1193,570 @@ async fn main() {
                 _ => None,
             };
 
-            match utils::update(
+            let verify = install_opts.verify;
+            let (progress, bars) = output::download_bars();
+            let result = utils::update(
                 client.clone(),
                 &library,
                 &slug,
                 install_opts,
                 &install_info,
                 selected_version,
+                Some(progress),
             )
-            .await
-            {
+            .await;
+            bars.await.ok();
+            match result {
                 Ok((info, Some(install_info))) => {
                     println!("{}", info);
-                    installed.insert(slug, install_info);
+                    let verified = !verify || verify_install(&slug, &install_info).await;
+                    if verified {
+                        history::record(HistoryAction::Update, &slug, true, &info);
+                    } else {
+                        history::record(
+                            HistoryAction::Update,
+                            &slug,
+                            false,
+                            "Updated, but failed verification",
+                        );
+                    }
+                    installed.insert_install(slug, install_info);
                     installed
                         .store()
                         .expect("Failed to update installed config");
+                    if !verified {
+                        std::process::exit(1);
+                    }
                 }
                 Ok((info, None)) => {
                     println!("{}", info);
                 }
+                Err(CarnivalError::Cancelled) => println!("Not updating {slug}"),
                 Err(err) => {
-                    println!("Failed to update {slug}: {:?}", err);
+                    println!("Failed to update {slug}: {}", err);
+                    history::record(HistoryAction::Update, &slug, false, &err.to_string());
                 }
             };
         }
         Commands::Launch {
             slug,
-            #[cfg(not(target_os = "windows"))]
-            wine,
-            #[cfg(not(target_os = "windows"))]
-            wine_prefix,
-            #[cfg(not(target_os = "windows"))]
-            no_wine,
-            wrapper,
+            os,
+            launch_opts,
         } => {
-            let installed = InstalledConfig::load().expect("Failed to load installed");
-            let library = LibraryConfig::load().expect("Failed to load library");
-            let install_info = match installed.get(&slug) {
-                Some(info) => info,
-                None => {
-                    println!("{slug} is not installed");
+            let launch_opts = LaunchOpts {
+                check,
+                offline: args.offline,
+                ..launch_opts
+            };
+            let mut installed = InstalledConfig::load_or_default();
+            let library = LibraryConfig::load_or_default();
+            let product = match utils::resolve_product(
+                &slug,
+                library
+                    .collection
+                    .iter()
+                    .filter(|p| installed.contains_key(&p.slugged_name)),
+            ) {
+                Ok(prod) => prod,
+                Err(err) => {
+                    println!("{err}");
                     return;
                 }
             };
-            let product = match library.collection.iter().find(|p| p.slugged_name == slug) {
-                Some(prod) => prod,
-                None => {
-                    println!("Couldn't find {slug} in library");
+            let slug = product.slugged_name.to_owned();
+            let install_info = match installed.get_install_mut(&slug, os.as_ref()) {
+                Ok(info) => info,
+                Err(err) => {
+                    println!("{err}");
                     return;
                 }
             };
-            match utils::launch(
-                &client,
-                product,
-                install_info,
-                #[cfg(not(target_os = "windows"))]
-                no_wine,
-                #[cfg(not(target_os = "windows"))]
-                wine,
-                #[cfg(not(target_os = "windows"))]
-                wine_prefix,
-                wrapper,
-            )
-            .await
-            {
-                Ok(Some(status)) => {
-                    println!("Process exited with: {}", status);
+            let previous_info = install_info.clone();
+            let result = utils::launch(&client, product, install_info, launch_opts).await;
+            if *install_info != previous_info && !check {
+                // Other commands may have changed the config while the game was running, so only
+                // what the launch changed is saved
+                let launched_info = install_info.clone();
+                let mut installed = InstalledConfig::load_or_default();
+                match installed.get_install_mut(&slug, Some(&launched_info.os)) {
+                    Ok(install_info) if install_info.install_path == launched_info.install_path => {
+                        install_info.merge_launch(&previous_info, &launched_info);
+                        installed
+                            .store()
+                            .expect("Failed to update installed config");
+                    }
+                    _ => debug!("{slug} was uninstalled while it ran, not saving its play time"),
+                }
+            }
+
+            match result {
+                Ok(Some(utils::Launched::Exited(status))) => {
+                    let message = format!("Process exited with: {}", status);
+                    println!("{message}");
+                    history::record(HistoryAction::Launch, &slug, status.success(), &message);
+                }
+                Ok(Some(utils::Launched::Detached(pid))) => {
+                    let message = format!("Started in the background with PID {pid}");
+                    println!("{message}");
+                    history::record(HistoryAction::Launch, &slug, true, &message);
                 }
+                Ok(None) if check => {}
                 Ok(None) => {
                     println!("Failed to launch {slug}");
+                    history::record(HistoryAction::Launch, &slug, false, "Failed to launch");
                 }
                 Err(err) => {
                     println!("Failed to launch {}: {:?}", slug, err);
+                    history::record(HistoryAction::Launch, &slug, false, &err.to_string());
                 }
             };
         }
-        Commands::Info { slug } => {
-            let library = LibraryConfig::load().expect("Failed to load library");
+        Commands::SetExe { slug, path, os } => {
+            let mut installed = InstalledConfig::load_or_default();
+            let install_info = match installed.get_install_mut(&slug, os.as_ref()) {
+                Ok(info) => info,
+                Err(err) => {
+                    println!("{err}");
+                    return;
+                }
+            };
+
+            let exe = match path {
+                Some(path) => {
+                    // Absolute paths are fine too, as long as they're in the install directory
+                    let path = match path.strip_prefix(&install_info.install_path) {
+                        Ok(relative) => relative.to_owned(),
+                        Err(_) if path.is_absolute() => {
+                            println!(
+                                "{} isn't in {}",
+                                path.display(),
+                                install_info.install_path.display()
+                            );
+                            return;
+                        }
+                        Err(_) => path,
+                    };
+                    if !install_info.install_path.join(&path).is_file() {
+                        println!(
+                            "{} doesn't exist in {}",
+                            path.display(),
+                            install_info.install_path.display()
+                        );
+                        return;
+                    }
+                    Some(path)
+                }
+                None => None,
+            };
+
+            let message = match &exe {
+                Some(exe) => format!("{slug} will launch {}", exe.display()),
+                None => format!("{slug} will launch the executable that's found automatically"),
+            };
+            if check {
+                println!("Would change the executable. {message}.");
+                return;
+            }
+
+            install_info.exe = exe;
+            installed
+                .store()
+                .expect("Failed to update installed config");
+            println!("{message}.");
+        }
+        Commands::Info {
+            slug,
+            versions,
+            latest,
+            files,
+            os,
+        } => {
+            // `--latest` and `--versions` are read by scripts, so their errors go to stderr
+            let library = LibraryConfig::load_or_default();
+            let slug = match utils::resolve_slug(&slug, &library, None) {
+                Ok(slug) => slug,
+                Err(err) => {
+                    eprintln!("{err}");
+                    std::process::exit(1);
+                }
+            };
             let product = match library.collection.iter().find(|p| p.slugged_name == slug) {
                 Some(p) => p,
                 None => {
-                    println!("{slug} is not in your library");
-                    return;
+                    eprintln!("{slug} is not in your library");
+                    std::process::exit(1);
                 }
             };
 
-            let installed = InstalledConfig::load().expect("Failed to load installed");
-            let install_info = installed.get(&slug);
+            if latest {
+                match product.get_latest_version(os.as_ref()) {
+                    Some(version) => println!("{}", version.version),
+                    None => {
+                        eprintln!("{slug} has no builds for your OS");
+                        std::process::exit(1);
+                    }
+                }
+                return;
+            }
+
+            if files {
+                let version = match product.get_latest_version(os.as_ref()) {
+                    Some(version) => version,
+                    None => {
+                        println!("{slug} has no builds for your OS");
+                        return;
+                    }
+                };
+                let records = match utils::build_files(&client, product, version).await {
+                    Ok(records) => records,
+                    Err(err) => {
+                        println!("Failed to get the files of {}: {}", version.version, err);
+                        return;
+                    }
+                };
+                let rows: Vec<Vec<String>> = records
+                    .into_iter()
+                    .map(|record| vec![human_bytes(record.size_in_bytes as f64), record.file_name])
+                    .collect();
+                println!("Files of {} ({}):", version.version, version.os);
+                output::print_table(&["SIZE", "FILE"], &rows);
+                return;
+            }
+
+            let product_versions = product
+                .version
+                .iter()
+                .filter(|v| os.as_ref().is_none_or(|os| &v.os == os));
+            if versions {
+                let mut any = false;
+                for version in product_versions {
+                    println!("{}", version.version);
+                    any = true;
+                }
+                if !any {
+                    match &os {
+                        Some(os) => eprintln!("{slug} has no {os} builds"),
+                        None => eprintln!("{slug} has no builds"),
+                    }
+                    std::process::exit(1);
+                }
+                return;
+            }
+
+            let installed = InstalledConfig::load_or_default();
+            if let Some(game) = installed.get(&slug) {
+                for install_info in game
+                    .iter()
+                    .filter(|info| os.as_ref().is_none_or(|os| &info.os == os))
+                {
+                    let status = match product.get_latest_version(Some(&install_info.os)) {
+                        Some(latest) if latest.version != install_info.version => {
+                            style(format!("update available: {}", latest.version))
+                                .yellow()
+                                .to_string()
+                        }
+                        Some(_) => style("up to date").green().to_string(),
+                        None => format!("no {} builds available anymore", install_info.os),
+                    };
+                    println!(
+                        "Installed: {} ({}), {status}\nPath: {}\nLast Played: {}\n",
+                        install_info.version,
+                        install_info.os,
+                        install_info.install_path.display(),
+                        match install_info.last_played {
+                            Some(last_played) => format!(
+                                "{} ({} in total)",
+                                last_played.format("%Y-%m-%d %H:%M"),
+                                HumanDuration(Duration::from_secs(install_info.play_time))
+                            ),
+                            None => "never".to_owned(),
+                        }
+                    );
+                }
+            }
 
             println!(
                 "Available Versions:\n{}",
-                product
-                    .version
-                    .iter()
+                product_versions
                     .map(|v| format!("\n{}", v))
                     .collect::<Vec<String>>()
                     .join("\n")
             );
         }
-        Commands::Verify { slug } => {
-            let installed = InstalledConfig::load().expect("Failed to load installed");
-            let install_info = match installed.get(&slug) {
-                Some(info) => info,
-                None => {
-                    println!("{slug} is not installed.");
+        Commands::History { slug } => {
+            let events = match history::read() {
+                Ok(events) => events,
+                Err(err) => {
+                    println!("Failed to read history: {:?}", err);
                     return;
                 }
             };
 
-            match utils::verify(&slug, install_info).await {
-                Ok(true) => {
-                    println!("{slug} passed verification.");
+            let events: Vec<_> = events
+                .iter()
+                .filter(|event| slug.as_ref().is_none_or(|slug| &event.slug == slug))
+                .collect();
+            if events.is_empty() {
+                println!("No history recorded yet");
+                return;
+            }
+
+            for event in events {
+                println!("{event}");
+            }
+        }
+        Commands::Diff {
+            slug,
+            old_version,
+            new_version,
+            summary,
+        } => match utils::diff(&slug, &old_version, &new_version, check).await {
+            Ok(diff) if summary => {
+                println!("Added: {} files", diff.count(ChangeTag::Added));
+                println!("Modified: {} files", diff.count(ChangeTag::Modified));
+                println!("Removed: {} files", diff.count(ChangeTag::Removed));
+                println!("Download Size: {}", human_bytes(diff.download_size));
+                println!(
+                    "Needed Space: {}{}",
+                    if diff.needed_space < 0f64 { "-" } else { "" },
+                    human_bytes(diff.needed_space.abs())
+                );
+            }
+            Ok(diff) if diff.records.is_empty() => {
+                println!("Builds {old_version} and {new_version} of {slug} have the same files.");
+            }
+            Ok(diff) => {
+                for record in diff.records {
+                    let tag = match record.tag {
+                        Some(ChangeTag::Added) => style("+").green(),
+                        Some(ChangeTag::Removed) => style("-").red(),
+                        _ => style("~").yellow(),
+                    };
+                    println!(
+                        "{tag} {} ({})",
+                        record.file_name,
+                        human_bytes(record.size_in_bytes as f64)
+                    );
                 }
-                Ok(false) => {
-                    println!("{slug} is corrupted. Please reinstall.");
+            }
+            Err(err) => {
+                println!("Failed to compare the builds: {}", err);
+            }
+        },
+        Commands::Verify {
+            slug,
+            os,
+            workers,
+            full,
+        } => {
+            let installed = InstalledConfig::load_or_default();
+            let library = LibraryConfig::load_or_default();
+            let slug = match utils::resolve_slug(&slug, &library, Some(&installed)) {
+                Ok(slug) => slug,
+                Err(err) => {
+                    println!("{err}");
+                    return;
                 }
+            };
+
+            let install_info = match installed.get_install(&slug, os.as_ref()) {
+                Ok(info) => info,
                 Err(err) => {
-                    println!("Failed to verify files: {}", err);
+                    println!("{err}");
+                    return;
+                }
+            };
+
+            verify_install_with(&slug, install_info, workers, full).await;
+        }
+        Commands::VerifyAll { workers, full } => {
+            let installed = InstalledConfig::load_or_default();
+            if installed.is_empty() {
+                println!("No games installed");
+                return;
+            }
+
+            let mut rows = vec![];
+            let mut any_failed = false;
+            for (slug, install_info) in installed.installs() {
+                println!("Verifying {slug} ({})...", install_info.os);
+                let (progress, bar) = output::verify_bar();
+                let result = utils::verify(slug, install_info, workers, full, Some(progress)).await;
+                bar.await.ok();
+                let result = match result {
+                    Ok(true) => style("passed").green().to_string(),
+                    Ok(false) => {
+                        any_failed = true;
+                        style("corrupted").red().to_string()
+                    }
+                    Err(err) => {
+                        any_failed = true;
+                        style(format!("failed to verify: {err}")).red().to_string()
+                    }
+                };
+                rows.push(vec![slug.to_owned(), install_info.os.to_string(), result]);
+            }
+
+            println!();
+            output::print_table(&["SLUG", "OS", "RESULT"], &rows);
+            if any_failed {
+                println!("\nReinstall corrupted games with `install --reinstall`.");
+                std::process::exit(1);
+            }
+        }
+        Commands::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                *PROJECT_NAME,
+                &mut std::io::stdout(),
+            );
+        }
+        Commands::CleanManifests => {
+            let installed = load_installed_or_exit();
+            match helpers::prune_build_manifests(&installed, None, check).await {
+                Ok((0, _)) => {
+                    println!("There are no manifests to remove.");
+                }
+                Ok((removed, removed_bytes)) => {
+                    println!(
+                        "{} {removed} manifests ({}).",
+                        if check { "Would remove" } else { "Removed" },
+                        human_bytes(removed_bytes as f64)
+                    );
+                }
+                Err(err) => {
+                    println!("Failed to clean manifests: {:?}", err);
+                }
+            }
+        }
+        Commands::Export { path } => {
+            let installed = InstalledConfig::load_or_default();
+            let installs = installed.installs().len();
+            match installed.store_to(&path) {
+                Ok(()) => println!("Exported {installs} installs to {}.", path.display()),
+                Err(err) => println!("Failed to export to {}: {}", path.display(), err),
+            }
+        }
+        Commands::Import { path } => {
+            if !path.is_file() {
+                println!("{} doesn't exist.", path.display());
+                return;
+            }
+            let imported = match InstalledConfig::load_from(&path) {
+                Ok(imported) => imported,
+                Err(err) => {
+                    println!("Failed to read {}: {}", path.display(), err);
+                    return;
+                }
+            };
+
+            let mut installed = load_installed_or_exit();
+            let mut added = 0;
+            for (slug, install_info) in imported.installs() {
+                if let Ok(existing) = installed.get_install(slug, Some(&install_info.os)) {
+                    println!(
+                        "{slug} is already installed for {} in {}, skipping.",
+                        install_info.os,
+                        existing.install_path.display()
+                    );
+                    continue;
+                }
+                // Records are only as good as the files they point to
+                if !install_info.install_path.is_dir() {
+                    println!(
+                        "{} doesn't exist, skipping {slug} ({}).",
+                        install_info.install_path.display(),
+                        install_info.os
+                    );
+                    continue;
+                }
+
+                println!(
+                    "{} {slug} ({}) in {}.",
+                    if check { "Would import" } else { "Imported" },
+                    install_info.os,
+                    install_info.install_path.display()
+                );
+                installed.insert_install(slug.to_owned(), install_info.to_owned());
+                added += 1;
+            }
+
+            if added == 0 {
+                println!("There was nothing to import.");
+            } else if !check {
+                installed
+                    .store()
+                    .expect("Failed to update installed config");
+            }
+        }
+        Commands::Clean { yes, base_path } => {
+            let mut installed = load_installed_or_exit();
+            let missing: Vec<(String, BuildOs, std::path::PathBuf)> = installed
+                .installs()
+                .into_iter()
+                .filter(|(_, install_info)| !install_info.install_path.exists())
+                .map(|(slug, install_info)| {
+                    (
+                        slug.to_owned(),
+                        install_info.os.clone(),
+                        install_info.install_path.to_owned(),
+                    )
+                })
+                .collect();
+
+            if missing.is_empty() {
+                println!("Every install's directory still exists.");
+            } else if !yes || check {
+                println!("These installs' directories are gone:");
+                for (slug, os, install_path) in &missing {
+                    println!("  {slug} ({os}): {}", install_path.display());
+                }
+                if !check {
+                    println!("Pass --yes to forget them.");
+                }
+            } else {
+                for (slug, os, install_path) in &missing {
+                    installed.remove_install(slug, Some(os)).ok();
+                    println!("Forgot {slug} ({os}), {} is gone.", install_path.display());
+                }
+                installed
+                    .store()
+                    .expect("Failed to update installed config");
+                for (slug, _, _) in &missing {
+                    if let Err(err) =
+                        helpers::prune_build_manifests(&installed, Some(slug), false).await
+                    {
+                        println!("Failed to remove the manifests of {slug}: {:?}", err);
+                    }
+                }
+            }
+
+            let base_path = base_path.unwrap_or(DEFAULT_BASE_INSTALL_PATH.to_owned());
+            match utils::untracked_install_dirs(&installed, &base_path).await {
+                Ok(untracked) if untracked.is_empty() => {}
+                Ok(untracked) => {
+                    println!(
+                        "These directories in {} aren't tracked installs:",
+                        base_path.display()
+                    );
+                    for path in untracked {
+                        println!("  {}", path.display());
+                    }
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => {
+                    println!("Failed to read {}: {:?}", base_path.display(), err);
                 }
             }
         }
     };
 
     drop(client);
+    if check {
+        return;
+    }
+
     let cookie_store = Arc::try_unwrap(cookie_store).expect("Failed to unwrap cookie store");
     let cookie_store = cookie_store
         .into_inner()
@@ -378,6 +1766,63 @@ async fn main() {
         .expect("Failed to save cookie config");
 }
 
+/// Loads the installed games for commands that remove or overwrite things based on them. Unlike
+/// `load_or_default`, a corrupted config isn't replaced with an empty one, which would make every
+/// install look gone.
+fn load_installed_or_exit() -> InstalledConfig {
+    match InstalledConfig::load() {
+        Ok(installed) => installed,
+        Err(err) => {
+            println!(
+                "Failed to load {}: {}",
+                InstalledConfig::get_config_path().display(),
+                config::error_reason(&err)
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Lists installs for messages, e.g. "syberia-ii-soundtrack is" or "a and b are"
+fn sharing_list(slugs: &[&String]) -> String {
+    let names: Vec<&str> = slugs.iter().map(|slug| slug.as_str()).collect();
+    match names.len() {
+        1 => format!("{} is", names[0]),
+        _ => format!("{} are", names.join(", ")),
+    }
+}
+
+/// Verifies an install that was just made, after `--verify`. Returns whether it passed.
+async fn verify_install(slug: &str, install_info: &InstallInfo) -> bool {
+    println!("Verifying {slug}...");
+    verify_install_with(slug, install_info, *DEFAULT_VERIFY_WORKERS, false).await
+}
+
+async fn verify_install_with(
+    slug: &str,
+    install_info: &InstallInfo,
+    workers: usize,
+    full: bool,
+) -> bool {
+    let (progress, bar) = output::verify_bar();
+    let result = utils::verify(slug, install_info, workers, full, Some(progress)).await;
+    bar.await.ok();
+    match result {
+        Ok(true) => {
+            println!("{slug} passed verification.");
+            true
+        }
+        Ok(false) => {
+            println!("{slug} is corrupted. Please reinstall.");
+            false
+        }
+        Err(err) => {
+            println!("Failed to verify files: {}", err);
+            false
+        }
+    }
+}
+
 fn save_user_info(
     SyncResult {
         user_config,
@@ -0,0 +1,342 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use console::StyledObject;
+use env_logger::WriteStyle;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use log::LevelFilter;
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{
+    constants::PROGRESS_EVENTS_BUFFER,
+    progress::{ProgressEvent, ProgressSender},
+};
+
+/// When to use colored output
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub(crate) enum ColorChoice {
+    /// Use colors when printing to a terminal, unless NO_COLOR is set
+    #[default]
+    Auto,
+    /// Always use colors, even when output is piped
+    Always,
+    /// Never use colors
+    Never,
+}
+
+impl ColorChoice {
+    /// Enables or disables colored output for the rest of the program
+    pub(crate) fn apply(self) {
+        let enabled = match self {
+            Self::Always => true,
+            Self::Never => false,
+            Self::Auto => {
+                if std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+                    false
+                } else {
+                    // Defer to TTY detection
+                    return;
+                }
+            }
+        };
+
+        console::set_colors_enabled(enabled);
+        console::set_colors_enabled_stderr(enabled);
+    }
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Hides progress bars and progress messages for the rest of the program
+pub(crate) fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub(crate) fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Sets up logging of diagnostic messages to stderr. Each `-v` shows one more level of detail,
+/// and `RUST_LOG` overrides the level entirely.
+pub(crate) fn init_logger(verbose: u8, quiet: bool, color: ColorChoice) {
+    let level = match (quiet, verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    let write_style = match color {
+        ColorChoice::Auto => WriteStyle::Auto,
+        ColorChoice::Always => WriteStyle::Always,
+        ColorChoice::Never => WriteStyle::Never,
+    };
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_env("RUST_LOG")
+        .write_style(write_style)
+        .format_timestamp(None)
+        .format_target(false)
+        .init();
+}
+
+/// Prints a summary for a batch of games, one line per outcome. Outcomes without any games are
+/// left out.
+pub(crate) fn print_summary(title: &str, outcomes: &[(StyledObject<&str>, &Vec<&String>)]) {
+    println!("\n{title}:");
+    for (label, slugs) in outcomes {
+        if slugs.is_empty() {
+            continue;
+        }
+
+        println!(
+            "{label}: {}",
+            slugs
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        );
+    }
+}
+
+/// Prints `rows` in columns lined up under `header`. The last column isn't padded, so it can be
+/// as long as it needs to be.
+pub(crate) fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|title| title.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let print_row = |cells: Vec<&str>| {
+        let last = cells.len().saturating_sub(1);
+        let line = cells
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| match i == last {
+                true => cell.to_string(),
+                false => format!("{cell:<width$}", width = widths[i]),
+            })
+            .collect::<Vec<String>>()
+            .join("  ");
+        println!("{line}");
+    };
+    print_row(header.to_vec());
+    for row in rows {
+        print_row(row.iter().map(|cell| cell.as_str()).collect());
+    }
+}
+
+/// Asks the user to pick one of `options` from a numbered list. Returns `None` if stdin isn't a
+/// terminal or the user didn't make a valid choice.
+pub(crate) fn choose<T: std::fmt::Display>(prompt: &str, options: &[T]) -> Option<usize> {
+    if !std::io::stdin().is_terminal() {
+        return None;
+    }
+
+    println!("{prompt}");
+    for (i, option) in options.iter().enumerate() {
+        println!("  {}) {}", i + 1, option);
+    }
+    print!("Select [1-{}]: ", options.len());
+    std::io::stdout().flush().ok()?;
+
+    let input = read_line()?;
+    match input.trim().parse::<usize>() {
+        Ok(choice) if choice >= 1 && choice <= options.len() => Some(choice - 1),
+        _ => None,
+    }
+}
+
+/// Asks the user a yes/no question. Returns `false` unless they answer yes, or if stdin isn't a
+/// terminal.
+pub(crate) fn confirm(prompt: &str) -> bool {
+    if !std::io::stdin().is_terminal() {
+        return false;
+    }
+
+    print!("{prompt} [y/N]: ");
+    if std::io::stdout().flush().is_err() {
+        return false;
+    }
+
+    let Some(input) = read_line() else {
+        return false;
+    };
+    matches!(input.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+static STDIN_LINES: OnceLock<Mutex<mpsc::Receiver<String>>> = OnceLock::new();
+
+/// Lines typed on stdin. They're all read by one thread, so prompts and the pause key never
+/// race each other for input, and a line only ever goes to one of them.
+fn stdin_lines() -> &'static Mutex<mpsc::Receiver<String>> {
+    STDIN_LINES.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut input = String::new();
+            while std::io::stdin()
+                .read_line(&mut input)
+                .is_ok_and(|read| read > 0)
+            {
+                if tx.send(std::mem::take(&mut input)).is_err() {
+                    break;
+                }
+            }
+        });
+        Mutex::new(rx)
+    })
+}
+
+/// Every progress bar is drawn through this, so bars from different places don't draw over each
+/// other. Hidden with `--quiet`.
+pub(crate) fn progress_bars() -> &'static MultiProgress {
+    static PROGRESS_BARS: OnceLock<MultiProgress> = OnceLock::new();
+    PROGRESS_BARS.get_or_init(|| {
+        if is_quiet() {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        } else {
+            MultiProgress::new()
+        }
+    })
+}
+
+/// Draws the download and disk bars of an install or update from the progress it reports. The
+/// bars are cleared once every sender is dropped.
+pub(crate) fn download_bars() -> (ProgressSender, JoinHandle<()>) {
+    let (progress, mut events) = tokio::sync::mpsc::channel(*PROGRESS_EVENTS_BUFFER);
+    let handle = tokio::spawn(async move {
+        let dl_sty =
+            ProgressStyle::with_template("{wide_msg} Download: {binary_bytes_per_sec}").unwrap();
+        let wr_sty = ProgressStyle::with_template(
+            "{wide_msg} Disk: {binary_bytes_per_sec}\n[{percent}%] {wide_bar} {bytes:>7}/{total_bytes:7} [{eta_precise}]",
+        )
+        .unwrap()
+        .progress_chars("##-");
+
+        let mut bars: Option<(ProgressBar, ProgressBar)> = None;
+        while let Some(event) = events.recv().await {
+            match (event, &bars) {
+                // Above the bars of the files being written, which are added meanwhile
+                (ProgressEvent::Started(total_bytes), _) => {
+                    let m = progress_bars();
+                    let dl_prog = m.insert(0, ProgressBar::new(0).with_style(dl_sty.clone()));
+                    let wrt_prog =
+                        m.insert(1, ProgressBar::new(total_bytes).with_style(wr_sty.clone()));
+                    bars = Some((dl_prog, wrt_prog));
+                }
+                (ProgressEvent::Downloaded(bytes), Some((dl_prog, _))) => dl_prog.inc(bytes),
+                (ProgressEvent::Wrote(bytes), Some((_, wrt_prog))) => wrt_prog.inc(bytes),
+                _ => {}
+            }
+        }
+        if let Some((dl_prog, wrt_prog)) = bars {
+            dl_prog.finish_and_clear();
+            wrt_prog.finish_and_clear();
+        }
+    });
+
+    (progress, handle)
+}
+
+/// Draws how many files a verify checked from the progress it reports. The bar is cleared once
+/// every sender is dropped.
+pub(crate) fn verify_bar() -> (ProgressSender, JoinHandle<()>) {
+    let (progress, mut events) = tokio::sync::mpsc::channel(*PROGRESS_EVENTS_BUFFER);
+    let handle = tokio::spawn(async move {
+        let mut prog: Option<ProgressBar> = None;
+        while let Some(event) = events.recv().await {
+            match (event, &prog) {
+                (ProgressEvent::Started(total), _) => {
+                    let bar = progress_bars().insert(0, ProgressBar::new(total));
+                    bar.set_style(
+                        ProgressStyle::with_template("{wide_msg} [{pos}/{len}]\n{wide_bar}")
+                            .unwrap()
+                            .progress_chars("##-"),
+                    );
+                    bar.set_message("Verifying files");
+                    prog = Some(bar);
+                }
+                (ProgressEvent::FileVerified(file_name, _), Some(prog)) => {
+                    prog.set_message(format!("Verified {file_name}"));
+                    prog.inc(1);
+                }
+                _ => {}
+            }
+        }
+        if let Some(prog) = prog {
+            prog.finish_and_clear();
+        }
+    });
+
+    (progress, handle)
+}
+
+/// Waits for the next line typed on stdin. `None` once stdin is closed.
+fn read_line() -> Option<String> {
+    stdin_lines().lock().unwrap().recv().ok()
+}
+
+/// Lets the user pause and resume downloads by pressing Enter, for as long as it's kept. Only
+/// listens if stdin is a terminal. Once it's dropped, lines go to prompts again.
+pub(crate) struct PauseListener {
+    paused: watch::Receiver<bool>,
+    stopped: Arc<AtomicBool>,
+}
+
+impl PauseListener {
+    pub(crate) fn new() -> PauseListener {
+        let (paused_tx, paused) = watch::channel(false);
+        let stopped = Arc::new(AtomicBool::new(false));
+        if !is_quiet() && std::io::stdin().is_terminal() {
+            println!("Press Enter to pause or resume downloads.");
+            let stopped = stopped.clone();
+            std::thread::spawn(move || {
+                // Checks now and then if it was dropped, so it doesn't take the next prompt's
+                // answer
+                while !stopped.load(Ordering::Relaxed) {
+                    let line = stdin_lines()
+                        .lock()
+                        .unwrap()
+                        .recv_timeout(Duration::from_millis(100));
+                    match line {
+                        Ok(_) => {
+                            let paused = !*paused_tx.borrow();
+                            paused_tx.send_replace(paused);
+                            if paused {
+                                println!("Downloads paused. Press Enter to resume.");
+                            } else {
+                                println!("Resuming downloads...");
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => {}
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                }
+            });
+        }
+
+        PauseListener { paused, stopped }
+    }
+
+    /// `true` while downloads are paused
+    pub(crate) fn paused(&self) -> watch::Receiver<bool> {
+        self.paused.clone()
+    }
+}
+
+impl Drop for PauseListener {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+    }
+}
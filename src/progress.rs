@@ -0,0 +1,26 @@
+use tokio::sync::mpsc::Sender;
+
+/// A structured progress update while installing, updating or verifying a game, so it can be
+/// shown by something other than the CLI's progress bars, like a GUI
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ProgressEvent {
+    /// Work started, and how much there is: bytes to write when installing or updating, files to
+    /// check when verifying
+    Started(u64),
+    /// A chunk of this many bytes was downloaded
+    Downloaded(u64),
+    /// This many bytes were written to disk
+    Wrote(u64),
+    /// A file was verified, and whether it's intact
+    FileVerified(String, bool),
+}
+
+pub(crate) type ProgressSender = Sender<ProgressEvent>;
+
+/// Sends `event` to `progress`, if there's anyone listening
+pub(crate) async fn report(progress: Option<&ProgressSender>, event: ProgressEvent) {
+    if let Some(progress) = progress {
+        // The receiver going away shouldn't stop an install
+        progress.send(event).await.ok();
+    }
+}
@@ -1,8 +1,10 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
+use chrono::{DateTime, Local};
+use indicatif::HumanDuration;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub(crate) struct InstallInfo {
     /// Directory where game was installed to
     pub(crate) install_path: PathBuf,
@@ -11,6 +13,27 @@ pub(crate) struct InstallInfo {
     /// OS the build is for
     #[serde(default)]
     pub(crate) os: api::BuildOs,
+    /// Executable to launch, relative to `install_path`. Set when the user picks one.
+    #[serde(default)]
+    pub(crate) exe: Option<PathBuf>,
+    /// WINE bin last used to launch this game
+    #[serde(default)]
+    pub(crate) wine_bin: Option<PathBuf>,
+    /// WINE prefix last used to launch this game
+    #[serde(default)]
+    pub(crate) wine_prefix: Option<PathBuf>,
+    /// Launch details from the server, saved so launching doesn't need to be online
+    #[serde(default)]
+    pub(crate) game_details: Option<api::GameDetails>,
+    /// How long the game has been running in total, in seconds
+    #[serde(default)]
+    pub(crate) play_time: u64,
+    /// When the game was last launched
+    #[serde(default)]
+    pub(crate) last_played: Option<DateTime<Local>>,
+    /// Glob patterns of files that weren't downloaded on purpose, see `--exclude`
+    #[serde(default)]
+    pub(crate) excluded: Vec<String>,
 }
 
 impl InstallInfo {
@@ -19,8 +42,46 @@ impl InstallInfo {
             install_path,
             version,
             os,
+            exe: None,
+            wine_bin: None,
+            wine_prefix: None,
+            game_details: None,
+            play_time: 0,
+            last_played: None,
+            excluded: vec![],
+        }
+    }
+
+    /// Applies what a launch changed, going from `before` to `after`, to this record. Play time is
+    /// added, so launches that overlap both count.
+    pub(crate) fn merge_launch(&mut self, before: &InstallInfo, after: &InstallInfo) {
+        self.play_time += after.play_time.saturating_sub(before.play_time);
+        if after.last_played != before.last_played {
+            self.last_played = after.last_played;
+        }
+        if after.exe != before.exe {
+            self.exe = after.exe.clone();
+        }
+        if after.game_details != before.game_details {
+            self.game_details = after.game_details.clone();
+        }
+        if after.wine_bin != before.wine_bin {
+            self.wine_bin = after.wine_bin.clone();
+        }
+        if after.wine_prefix != before.wine_prefix {
+            self.wine_prefix = after.wine_prefix.clone();
         }
     }
+
+    /// e.g. "played 2 hours, last on 2024-05-01 20:15". `None` if the game was never launched.
+    pub(crate) fn play_time_summary(&self) -> Option<String> {
+        let last_played = self.last_played?;
+        Some(format!(
+            "played {}, last on {}",
+            HumanDuration(Duration::from_secs(self.play_time)),
+            last_played.format("%Y-%m-%d %H:%M")
+        ))
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -32,7 +93,7 @@ pub(crate) struct BuildManifestRecord {
     #[serde(rename = "SHA")]
     pub(crate) sha: String,
     #[serde(rename = "Flags")]
-    pub(crate) flags: u8,
+    pub(crate) flags: ManifestFlags,
     #[serde(
         rename = "File Name",
         deserialize_with = "from_latin1_str",
@@ -44,8 +105,9 @@ pub(crate) struct BuildManifestRecord {
 }
 
 impl BuildManifestRecord {
+    /// Directories have no chunks of their own
     pub(crate) fn is_directory(&self) -> bool {
-        self.flags == 40
+        self.flags == ManifestFlags::DIRECTORY
     }
 
     pub(crate) fn is_empty(&self) -> bool {
@@ -53,6 +115,26 @@ impl BuildManifestRecord {
     }
 }
 
+/// The `Flags` column of a build manifest, which says what kind of entry a record is. Values
+/// without a name here are kept as they are, so they're written back unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub(crate) struct ManifestFlags(u8);
+
+impl ManifestFlags {
+    /// A file. Builds haven't been seen marking executables with another value, so there's no
+    /// executable flag to honor. They're found by looking at the installed files instead, e.g. a
+    /// Mac app's Info.plist, and marked executable then.
+    pub(crate) const FILE: ManifestFlags = ManifestFlags(0);
+    /// The entry is a directory
+    pub(crate) const DIRECTORY: ManifestFlags = ManifestFlags(40);
+
+    /// Whether this is a value we know the meaning of
+    pub(crate) fn is_known(&self) -> bool {
+        *self == ManifestFlags::FILE || *self == ManifestFlags::DIRECTORY
+    }
+}
+
 #[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub(crate) enum ChangeTag {
     Added,
@@ -95,13 +177,6 @@ pub(crate) mod api {
 
     use crate::config::{LibraryConfig, UserConfig};
 
-    #[derive(Debug, Serialize)]
-    pub(crate) struct LatestBuildNumberPayload {
-        dev_id: String,
-        id_key_name: String,
-        os_selected: String,
-    }
-
     #[derive(Debug, Deserialize)]
     pub(crate) struct GameDetailsResponse {
         pub(crate) status: String,
@@ -109,7 +184,7 @@ pub(crate) mod api {
         pub(crate) product_data: GameDetails,
     }
 
-    #[derive(Debug, Deserialize, Serialize)]
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
     pub(crate) struct GameDetails {
         pub(crate) exe_path: Option<String>,
         pub(crate) args: Option<String>,
@@ -169,16 +244,28 @@ pub(crate) mod api {
     }
 
     impl Product {
+        /// Latest build for `os`. If `os` isn't set, the builds that run best on this OS are
+        /// preferred, see [`BuildOs::preferred`].
         pub(crate) fn get_latest_version(&self, os: Option<&BuildOs>) -> Option<&ProductVersion> {
+            match os {
+                Some(os) => self.get_preferred_version(std::slice::from_ref(os)),
+                None => self.get_preferred_version(&BuildOs::preferred()),
+            }
+        }
+
+        /// Latest build for the first OS in `preference` that has one
+        pub(crate) fn get_preferred_version(
+            &self,
+            preference: &[BuildOs],
+        ) -> Option<&ProductVersion> {
+            preference
+                .iter()
+                .find_map(|os| self.get_latest_version_for(os))
+        }
+
+        fn get_latest_version_for(&self, os: &BuildOs) -> Option<&ProductVersion> {
             self.version.iter().fold(None, |acc, version| {
-                let valid_os = match os {
-                    Some(build_os) => version.os == *build_os,
-                    #[cfg(target_os = "macos")]
-                    None => version.os == BuildOs::Mac,
-                    #[cfg(not(target_os = "macos"))]
-                    None => version.os == BuildOs::Windows,
-                };
-                if !valid_os {
+                if version.os != *os {
                     return acc;
                 }
 
@@ -206,8 +293,9 @@ pub(crate) mod api {
         pub(crate) text: String,
     }
 
-    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+    #[derive(Debug, Default, Serialize, Deserialize, PartialEq, Clone)]
     pub(crate) enum BuildOs {
+        #[default]
         #[serde(rename = "win")]
         Windows,
         #[serde(rename = "lin")]
@@ -216,9 +304,34 @@ pub(crate) mod api {
         Mac,
     }
 
-    impl Default for BuildOs {
-        fn default() -> Self {
-            Self::Windows
+    impl BuildOs {
+        /// The OSes whose builds can run on this OS, best first. Native builds come first, and
+        /// Windows builds can still run through WINE.
+        pub(crate) fn preferred() -> Vec<BuildOs> {
+            #[cfg(target_os = "macos")]
+            return vec![BuildOs::Mac, BuildOs::Windows];
+            #[cfg(target_os = "linux")]
+            return vec![BuildOs::Linux, BuildOs::Windows];
+            #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+            return vec![BuildOs::Windows];
+        }
+
+        /// The OS's name, e.g. "macOS"
+        pub(crate) fn name(&self) -> &'static str {
+            match self {
+                BuildOs::Windows => "Windows",
+                BuildOs::Linux => "Linux",
+                BuildOs::Mac => "macOS",
+            }
+        }
+
+        /// Whether builds for this OS run on this OS without WINE or a wrapper
+        pub(crate) fn is_native(&self) -> bool {
+            match self {
+                BuildOs::Windows => cfg!(target_os = "windows"),
+                BuildOs::Linux => cfg!(target_os = "linux"),
+                BuildOs::Mac => cfg!(target_os = "macos"),
+            }
         }
     }
 
@@ -246,15 +359,7 @@ pub(crate) mod api {
         fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             writeln!(f, "[{}]", self.version)?;
             writeln!(f, "Build Date: {}", self.date)?;
-            writeln!(
-                f,
-                "Platform: {}",
-                match self.os {
-                    BuildOs::Windows => "Windows",
-                    BuildOs::Linux => "Linux",
-                    BuildOs::Mac => "macOS",
-                }
-            )?;
+            writeln!(f, "Platform: {}", self.os.name())?;
             if !self.text.is_empty() {
                 writeln!(f, "About:\n\n{}", self.text)?;
             }
@@ -1,161 +1,633 @@
-use std::{collections::HashMap, path::PathBuf, process::ExitStatus, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    io::IsTerminal,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use bytes::Bytes;
+use chrono::{DateTime, Local};
+use console::style;
+use futures::{stream, StreamExt};
+use glob::Pattern;
 use human_bytes::human_bytes;
+use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
+use log::debug;
 use os_path::OsPath;
 use regex::Regex;
 use shlex::split;
-use tokio::task::JoinHandle;
+use tokio::{sync::Semaphore, task::JoinHandle};
 
+#[cfg(target_os = "linux")]
+use crate::helpers::linux::{find_executables_recursive, mark_as_executable};
 #[cfg(target_os = "macos")]
 use crate::helpers::mac::{find_app_recursive, find_info_plist, MacAppExecutables};
+#[cfg(target_os = "windows")]
+use crate::helpers::windows;
 use crate::{
     api,
-    cli::InstallOpts,
-    config::{GalaConfig, InstalledConfig, LibraryConfig},
+    cli::{InstallOpts, LaunchOpts},
+    config::{GalaConfig, InstalledConfig, InstalledGames, LibraryConfig},
+    constants::{
+        DEFAULT_VERIFY_WORKERS, LARGE_DOWNLOAD_SIZE, MIN_RELIABLE_PLAY_TIME, PROJECT_DIRS,
+    },
+    error::CarnivalError,
     helpers::{
-        build_from_manifest, find_exe_recursive, read_build_manifest,
-        read_or_generate_delta_chunks_manifest, read_or_generate_delta_manifest,
-        store_build_manifest, verify_file_hash,
+        available_space, build_from_manifest, copy_dir_recursive, delta_download_size, dir_size,
+        file_sizes_match_manifest, find_exes_recursive, generate_delta_manifest, is_excluded,
+        manifest_disk_size, manifest_file_path, parse_build_manifest, part_file_path,
+        read_build_manifest, read_fresh_build_manifest, read_or_generate_delta_chunks_manifest,
+        read_or_generate_delta_manifest, store_build_manifest, verify_file_hash,
     },
+    output::{self, choose, is_quiet, progress_bars},
+    progress::{report, ProgressEvent, ProgressSender},
     shared::models::{
         api::{BuildOs, Product, ProductVersion},
         BuildManifestRecord, ChangeTag, InstallInfo,
     },
+    verify_cache::{VerifiedFile, VerifyCache},
 };
 
 // TODO: Refactor info printing and chunk downloading to separate functions
-pub(crate) async fn install<'a>(
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn install(
     client: reqwest::Client,
     slug: &String,
     install_path: &PathBuf,
     install_opts: InstallOpts,
     version: Option<&ProductVersion>,
-    os: Option<BuildOs>,
-) -> Result<Result<(String, Option<InstallInfo>), &'a str>, reqwest::Error> {
-    let library = LibraryConfig::load().expect("Failed to load library");
+    os_preference: &[BuildOs],
+    keep_partial: bool,
+    progress: Option<ProgressSender>,
+) -> Result<Result<(String, Option<InstallInfo>), String>, CarnivalError> {
+    let library = LibraryConfig::load_or_default();
     let product = match library.collection.iter().find(|p| p.slugged_name == *slug) {
         Some(product) => product,
         None => {
-            return Ok(Err("Could not find game in library"));
+            return Ok(Err("Could not find game in library".to_owned()));
         }
     };
 
     let build_version = match version {
         Some(selected) => selected,
-        None => match product.get_latest_version(os.as_ref()) {
+        None => match product.get_preferred_version(os_preference) {
             Some(latest) => latest,
             None => {
-                return Ok(Err("Failed to fetch latest build number. Cannot install."));
+                return Ok(Err(
+                    "Failed to fetch latest build number. Cannot install.".to_owned()
+                ));
             }
         },
     };
     println!("Found game. Installing build version {}...", build_version);
 
-    println!("Fetching build manifest...");
-    let build_manifest = api::product::get_build_manifest(&client, product, build_version).await?;
-    store_build_manifest(
-        &build_manifest,
+    if install_opts.manifest_only {
+        if install_opts.check {
+            return Ok(Ok((
+                format!("Would store the manifests of {slug} build {build_version}"),
+                None,
+            )));
+        }
+
+        println!("Fetching build manifests...");
+        for (manifest, file_suffix) in [
+            (
+                api::product::get_build_manifest(&client, product, build_version).await?,
+                "manifest",
+            ),
+            (
+                api::product::get_build_manifest_chunks(&client, product, build_version).await?,
+                "manifest_chunks",
+            ),
+        ] {
+            store_build_manifest(
+                &manifest,
+                &build_version.version,
+                &product.slugged_name,
+                file_suffix,
+            )
+            .await?;
+        }
+        return Ok(Ok((
+            format!("Stored the manifests of {slug} build {build_version}"),
+            None,
+        )));
+    }
+
+    let build_manifest = match read_cached_build_manifest(
         &build_version.version,
         &product.slugged_name,
         "manifest",
+        install_opts.max_manifest_age,
     )
     .await
-    .expect("Failed to save build manifest");
-
-    if install_opts.info {
-        let mut build_manifest_rdr = csv::Reader::from_reader(&build_manifest[..]);
-        let download_size = build_manifest_rdr
-            .byte_records()
-            .map(|r| {
-                let mut record = r.expect("Failed to get byte record");
-                record.push_field(b"");
-                record.deserialize::<BuildManifestRecord>(None)
-            })
-            .fold(0f64, |acc, record| match record {
-                Ok(record) => acc + record.size_in_bytes as f64,
-                Err(_) => acc,
-            });
+    {
+        Some(cached) => cached,
+        None => {
+            println!("Fetching build manifest...");
+            let build_manifest =
+                api::product::get_build_manifest(&client, product, build_version).await?;
+            if !install_opts.check {
+                store_build_manifest(
+                    &build_manifest,
+                    &build_version.version,
+                    &product.slugged_name,
+                    "manifest",
+                )
+                .await?;
+            }
+            build_manifest
+        }
+    };
 
+    let download_size = manifest_disk_size(&build_manifest[..])?;
+    if install_opts.info || install_opts.check {
         let mut buf = String::new();
+        if install_opts.check {
+            buf.push_str(&format!(
+                "Would install {slug} build {} ({}) into {}\n",
+                build_version.version,
+                build_version.os,
+                install_path.display()
+            ));
+        }
         buf.push_str(&format!("Download Size: {}", human_bytes(download_size)));
         buf.push_str(&format!("\nDisk Size: {}", human_bytes(download_size)));
         return Ok(Ok((buf, None)));
     }
 
-    println!("Fetching build manifest chunks...");
-    let build_manifest_chunks =
-        api::product::get_build_manifest_chunks(&client, product, build_version).await?;
-    store_build_manifest(
-        &build_manifest_chunks,
+    if let Err(err) = check_available_space(install_path, download_size) {
+        return Ok(Err(err));
+    }
+    if !confirm_download(download_size, download_size, install_opts.yes) {
+        return Err(CarnivalError::Cancelled);
+    }
+
+    let build_manifest_chunks = match read_cached_build_manifest(
         &build_version.version,
         &product.slugged_name,
         "manifest_chunks",
+        install_opts.max_manifest_age,
     )
     .await
-    .expect("Failed to save build manifest chunks");
+    {
+        Some(cached) => cached,
+        None => {
+            println!("Fetching build manifest chunks...");
+            let build_manifest_chunks =
+                api::product::get_build_manifest_chunks(&client, product, build_version).await?;
+            store_build_manifest(
+                &build_manifest_chunks,
+                &build_version.version,
+                &product.slugged_name,
+                "manifest_chunks",
+            )
+            .await?;
+            build_manifest_chunks
+        }
+    };
 
     let product_arc = Arc::new(product.clone());
     let os_arc = Arc::new(build_version.os.to_owned());
 
+    let excluded: Vec<String> = install_opts
+        .exclude
+        .iter()
+        .map(|pattern| pattern.as_str().to_owned())
+        .collect();
+    // Only a directory created by this install is removed if it fails
+    let install_path_existed = install_path.exists();
     println!("Installing game from manifest...");
-    let result = build_from_manifest(
-        client,
+    if let Err(err) = build_from_manifest(
+        client.clone(),
         product_arc,
         os_arc,
         &build_manifest[..],
         &build_manifest_chunks[..],
+        None,
         install_path.into(),
         install_opts,
+        progress,
     )
     .await
-    .expect("Failed to build from manifest");
-
-    match result {
-        true => {
-            let install_info = InstallInfo::new(
-                install_path.to_owned(),
-                build_version.version.to_owned(),
-                build_version.os.to_owned(),
+    {
+        if keep_partial {
+            println!("Keeping the partial install in {}", install_path.display());
+        } else if install_path_existed {
+            println!(
+                "{} already existed before installing, so the partial install in it wasn't removed",
+                install_path.display()
+            );
+        } else {
+            println!(
+                "Removing the partial install in {}...",
+                install_path.display()
             );
-            Ok(Ok((
-                format!("Successfully installed {} ({})", slug, build_version),
-                Some(install_info),
-            )))
+            if let Err(remove_err) = uninstall(install_path).await {
+                println!(
+                    "Failed to remove {}: {:?}",
+                    install_path.display(),
+                    remove_err
+                );
+            }
+        }
+        return Err(err);
+    }
+
+    let mut install_info = InstallInfo::new(
+        install_path.to_owned(),
+        build_version.version.to_owned(),
+        build_version.os.to_owned(),
+    );
+    install_info.excluded = excluded;
+    // Saved now, so the game can be launched offline
+    install_info.game_details = match api::product::get_game_details(&client, product).await {
+        Ok(details) => details,
+        Err(err) => {
+            println!("Failed to fetch game details: {:?}", err);
+            None
+        }
+    };
+    Ok(Ok((
+        format!("Successfully installed {} ({})", slug, build_version),
+        Some(install_info),
+    )))
+}
+
+/// Resolves `query` to one of `candidates`. An exact slug match always wins. Otherwise, games
+/// whose slug or name contain `query` (ignoring case) are considered, and the user is asked to
+/// pick one if there are several.
+pub(crate) fn resolve_product<'a>(
+    query: &str,
+    candidates: impl Iterator<Item = &'a Product>,
+) -> Result<&'a Product, String> {
+    let query_lower = query.to_lowercase();
+    let mut matches = vec![];
+    for product in candidates {
+        if product.slugged_name == query {
+            return Ok(product);
+        }
+
+        if matches_query(product, &query_lower) {
+            matches.push(product);
+        }
+    }
+
+    match matches.len() {
+        0 => Err(format!("No game matches {query}")),
+        1 => Ok(matches[0]),
+        _ => match choose(&format!("Multiple games match {query}:"), &matches) {
+            Some(choice) => Ok(matches[choice]),
+            None => Err(format!(
+                "Multiple games match {query}, please be more specific:\n{}",
+                matches
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            )),
+        },
+    }
+}
+
+fn matches_query(product: &Product, query_lower: &str) -> bool {
+    product.slugged_name.to_lowercase().contains(query_lower)
+        || product.name.to_lowercase().contains(query_lower)
+}
+
+/// Resolves `query` to a slug with [`resolve_product`], only among the installed games if
+/// `installed` is set. If nothing matches, `query` is returned as is, so the caller reports it
+/// like any other unknown slug. Fails if several games match and the user didn't pick one.
+pub(crate) fn resolve_slug(
+    query: &str,
+    library: &LibraryConfig,
+    installed: Option<&InstalledConfig>,
+) -> Result<String, String> {
+    // Installed games that aren't in the library anymore can still be found by their slug
+    if installed.is_some_and(|installed| installed.contains_key(query)) {
+        return Ok(query.to_owned());
+    }
+
+    let query_lower = query.to_lowercase();
+    let candidates: Vec<&Product> = library
+        .collection
+        .iter()
+        .filter(|p| installed.is_none_or(|installed| installed.contains_key(&p.slugged_name)))
+        .filter(|p| matches_query(p, &query_lower))
+        .collect();
+    if candidates.is_empty() {
+        return Ok(query.to_owned());
+    }
+
+    let product = resolve_product(query, candidates.into_iter())?;
+    // On stderr, so it doesn't end up in the output of commands meant for scripts
+    if product.slugged_name != query && !is_quiet() {
+        eprintln!("Using {product}");
+    }
+
+    Ok(product.slugged_name.to_owned())
+}
+
+/// The DLC of `product` in the library. IndieGala lists DLC as products of their own, which are
+/// told apart by sharing the game's namespace and having a slug that starts with the game's.
+pub(crate) fn find_dlcs<'a>(product: &Product, library: &'a LibraryConfig) -> Vec<&'a Product> {
+    let prefix = format!("{}-", product.slugged_name);
+    library
+        .collection
+        .iter()
+        .filter(|p| p.namespace == product.namespace && p.slugged_name.starts_with(&prefix))
+        .collect()
+}
+
+/// The slugs of the other installs in `install_path`, like DLC installed along with its game
+pub(crate) fn installs_sharing<'a>(
+    installed: &'a InstalledConfig,
+    install_path: &Path,
+    slug: &str,
+) -> Vec<&'a String> {
+    installed
+        .installs()
+        .into_iter()
+        .filter(|(other, info)| *other != slug && info.install_path == install_path)
+        .map(|(other, _)| other)
+        .collect()
+}
+
+/// Makes sure there are at least `needed_space` bytes free where the game is installed
+fn check_available_space(install_path: &Path, needed_space: f64) -> Result<(), String> {
+    let available = match available_space(install_path) {
+        Ok(available) => available as f64,
+        Err(err) => {
+            println!("Couldn't check available disk space: {:?}", err);
+            return Ok(());
         }
-        false => Ok(Err(
-            "Some chunks failed verification. Failed to install game.",
-        )),
+    };
+
+    if needed_space > available {
+        return Err(format!(
+            "Not enough disk space in {}: {} needed, {} available",
+            install_path.display(),
+            human_bytes(needed_space),
+            human_bytes(available)
+        ));
     }
+
+    Ok(())
+}
+
+/// Reads a stored build manifest if it was fetched less than `max_age` ago. Without a `max_age`,
+/// manifests are always fetched again.
+async fn read_cached_build_manifest(
+    build_number: &str,
+    product_slug: &str,
+    file_suffix: &str,
+    max_age: Option<Duration>,
+) -> Option<Bytes> {
+    let manifest =
+        read_fresh_build_manifest(build_number, product_slug, file_suffix, max_age?).await;
+    match manifest {
+        Ok(manifest) => {
+            debug!("Using cached build {file_suffix}");
+            Some(Bytes::from(manifest))
+        }
+        Err(_) => None,
+    }
+}
+
+/// Disk space the latest build of each product takes up, in the same order. Stored build manifests
+/// are used when there are some, and the others are fetched a few at a time without storing them.
+/// A size is `None` if the product has no builds or the manifest couldn't be fetched or read.
+pub(crate) async fn latest_build_sizes(
+    client: &reqwest::Client,
+    products: &[Product],
+) -> Vec<Option<f64>> {
+    stream::iter(products)
+        .map(|product| async move {
+            let version = product.get_latest_version(None)?;
+            let manifest = match read_build_manifest(
+                &version.version,
+                &product.slugged_name,
+                "manifest",
+            )
+            .await
+            {
+                Ok(manifest) => Bytes::from(manifest),
+                Err(_) => match api::product::get_build_manifest(client, product, version).await {
+                    Ok(manifest) => manifest,
+                    Err(err) => {
+                        debug!("Failed to fetch the manifest of {}: {}", product, err);
+                        return None;
+                    }
+                },
+            };
+            match manifest_disk_size(&manifest[..]) {
+                Ok(size) => Some(size),
+                Err(err) => {
+                    debug!("Failed to read the manifest of {}: {}", product, err);
+                    None
+                }
+            }
+        })
+        .buffered(8)
+        .collect()
+        .await
+}
+
+/// The files of a build, without its directories. The manifest is only fetched, not stored.
+pub(crate) async fn build_files(
+    client: &reqwest::Client,
+    product: &Product,
+    build_version: &ProductVersion,
+) -> Result<Vec<BuildManifestRecord>, CarnivalError> {
+    let manifest = api::product::get_build_manifest(client, product, build_version).await?;
+    let records = tokio::task::spawn_blocking(move || parse_build_manifest(&manifest)).await??;
+
+    Ok(records
+        .into_iter()
+        .filter(|record| !record.is_directory())
+        .collect())
 }
 
 pub(crate) async fn uninstall(install_path: &PathBuf) -> tokio::io::Result<()> {
     tokio::fs::remove_dir_all(install_path).await
 }
 
+/// Removes the files of an install that shares its directory with other installs, like a game
+/// and its DLC, going by its build manifest. Files that the other installs' manifests list too
+/// are kept, and so are directories that still have something in them.
+pub(crate) async fn uninstall_files(
+    slug: &str,
+    install_info: &InstallInfo,
+    installed: &InstalledConfig,
+) -> tokio::io::Result<()> {
+    let manifest_records = |slug: String, version: String| async move {
+        let manifest = read_build_manifest(&version, &slug, "manifest").await?;
+        parse_build_manifest(&manifest).map_err(std::io::Error::other)
+    };
+
+    let mut kept = HashSet::new();
+    for (other, info) in installed.installs() {
+        if other != slug && info.install_path == install_info.install_path {
+            let records = manifest_records(other.to_owned(), info.version.to_owned()).await?;
+            kept.extend(records.into_iter().map(|record| record.file_name));
+        }
+    }
+
+    let mut directories = vec![];
+    let records = manifest_records(slug.to_owned(), install_info.version.to_owned()).await?;
+    for record in records {
+        if kept.contains(&record.file_name) {
+            continue;
+        }
+
+        let path = manifest_file_path(&install_info.install_path, &record.file_name);
+        if record.is_directory() {
+            directories.push(path);
+            continue;
+        }
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+    }
+
+    // Deepest first, so a directory's subdirectories are gone by the time it's reached
+    directories.sort_by_key(|path| std::cmp::Reverse(path.components().count()));
+    for directory in directories {
+        if let Err(err) = tokio::fs::remove_dir(&directory).await {
+            debug!("Keeping {}: {}", directory.display(), err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a list of games to install, one `slug` or `slug@version` per line. Blank lines and
+/// comments starting with `#` are skipped.
+pub(crate) async fn read_slug_list(
+    path: &Path,
+) -> tokio::io::Result<Vec<(String, Option<String>)>> {
+    let contents = tokio::fs::read_to_string(path).await?;
+
+    Ok(contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| match line.split_once('@') {
+            Some((slug, version)) => (slug.trim().to_owned(), Some(version.trim().to_owned())),
+            None => (line.to_owned(), None),
+        })
+        .collect())
+}
+
+/// Lists the directories in `base_path` that no install in `installed` uses
+pub(crate) async fn untracked_install_dirs(
+    installed: &InstalledConfig,
+    base_path: &Path,
+) -> tokio::io::Result<Vec<PathBuf>> {
+    let install_paths: Vec<PathBuf> = installed
+        .installs()
+        .into_iter()
+        .map(|(_, install_info)| {
+            std::fs::canonicalize(&install_info.install_path)
+                .unwrap_or_else(|_| install_info.install_path.to_owned())
+        })
+        .collect();
+
+    let mut untracked = vec![];
+    let mut entries = tokio::fs::read_dir(base_path).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let path = entry.path();
+        let canonical_path = tokio::fs::canonicalize(&path)
+            .await
+            .unwrap_or_else(|_| path.to_owned());
+        if !install_paths.contains(&canonical_path) {
+            untracked.push(path);
+        }
+    }
+    untracked.sort();
+
+    Ok(untracked)
+}
+
+/// Moves an install from `from` to `to`. Tries a rename first, and falls back to copying and
+/// deleting the original files when `to` is on a different filesystem.
+pub(crate) async fn move_install(from: &Path, to: &Path) -> tokio::io::Result<()> {
+    if tokio::fs::try_exists(to).await? {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{} already exists", to.display()),
+        ));
+    }
+    if let Some(parent) = to.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    match tokio::fs::rename(from, to).await {
+        Ok(()) => return Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::CrossesDevices => {
+            println!("Target is on a different filesystem. Copying files instead...");
+        }
+        Err(err) => return Err(err),
+    };
+
+    let needed_space = dir_size(from).await?;
+    let free_space = available_space(to)?;
+    if needed_space > free_space {
+        return Err(std::io::Error::other(format!(
+            "Not enough free space in {}: {} needed, {} available",
+            to.display(),
+            human_bytes(needed_space as f64),
+            human_bytes(free_space as f64)
+        )));
+    }
+
+    if let Err(err) = copy_dir_recursive(from, to).await {
+        // Don't leave a partial copy behind
+        tokio::fs::remove_dir_all(to).await.ok();
+        return Err(err);
+    }
+    tokio::fs::remove_dir_all(from).await
+}
+
+/// Lists every install that isn't on the latest version of its OS, along with that version
 pub(crate) async fn check_updates(
-    library: LibraryConfig,
-    installed: InstalledConfig,
-) -> tokio::io::Result<HashMap<String, String>> {
-    let mut available_updates = HashMap::new();
-    for (slug, info) in installed {
-        println!("Checking if {slug} has updates...");
-        let product = match library.collection.iter().find(|p| p.slugged_name == slug) {
+    library: &LibraryConfig,
+    installed: &InstalledConfig,
+) -> tokio::io::Result<Vec<(String, BuildOs, ProductVersion)>> {
+    let mut available_updates = vec![];
+    for (slug, info) in installed.installs() {
+        debug!("Checking if {slug} ({}) has updates...", info.os);
+        let product = match library.collection.iter().find(|p| &p.slugged_name == slug) {
             Some(p) => p,
             None => {
-                println!("Couldn't find {slug} in library. Try running `sync` first.");
+                if !is_quiet() {
+                    println!("Couldn't find {slug} in library. Try running `sync` first.");
+                }
                 continue;
             }
         };
         let latest_version = match product.get_latest_version(Some(&info.os)) {
             Some(v) => v,
             None => {
-                println!("Couldn't find the latest version of {slug}");
+                if !is_quiet() {
+                    println!("Couldn't find the latest version of {slug}");
+                }
                 continue;
             }
         };
 
         if info.version != latest_version.version {
-            available_updates.insert(slug, latest_version.version.to_owned());
+            available_updates.push((
+                slug.to_owned(),
+                info.os.to_owned(),
+                latest_version.to_owned(),
+            ));
         }
     }
     Ok(available_updates)
@@ -168,7 +640,8 @@ pub(crate) async fn update(
     install_opts: InstallOpts,
     install_info: &InstallInfo,
     selected_version: Option<&ProductVersion>,
-) -> tokio::io::Result<(String, Option<InstallInfo>)> {
+    progress: Option<ProgressSender>,
+) -> Result<(String, Option<InstallInfo>), CarnivalError> {
     let product = match library.collection.iter().find(|p| &p.slugged_name == slug) {
         Some(p) => p,
         None => {
@@ -194,103 +667,127 @@ pub(crate) async fn update(
 
     let old_manifest = read_build_manifest(&install_info.version, slug, "manifest").await?;
 
-    println!("Fetching {} build manifest...", version);
-    let new_manifest = match api::product::get_build_manifest(&client, product, version).await {
-        Ok(m) => m,
-        Err(err) => {
-            return Ok((format!("Failed to fetch build manifest: {:?}", err), None));
+    let new_manifest = match read_cached_build_manifest(
+        &version.version,
+        slug,
+        "manifest",
+        install_opts.max_manifest_age,
+    )
+    .await
+    {
+        Some(cached) => cached,
+        None => {
+            println!("Fetching {} build manifest...", version);
+            let new_manifest = api::product::get_build_manifest(&client, product, version).await?;
+            if !install_opts.check {
+                store_build_manifest(&new_manifest, &version.version, slug, "manifest").await?;
+            }
+            new_manifest
         }
     };
-    store_build_manifest(&new_manifest, &version.version, slug, "manifest").await?;
-    let new_manifest_chunks =
-        match api::product::get_build_manifest_chunks(&client, product, version).await {
-            Ok(m) => m,
-            Err(err) => {
-                return Ok((
-                    format!("Failed to fetch build manifest chunks: {:?}", err),
-                    None,
-                ));
-            }
-        };
-    store_build_manifest(
-        &new_manifest_chunks,
+    let delta_manifest = if install_opts.check {
+        generate_delta_manifest(&old_manifest[..], &new_manifest[..])
+    } else {
+        read_or_generate_delta_manifest(
+            slug,
+            &old_manifest[..],
+            &new_manifest[..],
+            &install_info.version,
+            &version.version,
+            install_opts.max_manifest_age,
+        )
+        .await?
+    };
+    let disk_size = manifest_disk_size(&new_manifest[..])?;
+    let old_disk_size = manifest_disk_size(&old_manifest[..])?;
+    let needed_space = disk_size - old_disk_size;
+    let download_size = parse_build_manifest(&delta_manifest[..])?
+        .iter()
+        .filter(|record| record.tag != Some(ChangeTag::Removed))
+        .map(|record| record.size_in_bytes as f64)
+        .sum::<f64>();
+
+    if install_opts.info || install_opts.check {
+        let mut buf = String::new();
+        if install_opts.check {
+            buf.push_str(&format!(
+                "Would update {slug} from build {} to build {} in {}\n",
+                install_info.version,
+                version.version,
+                install_info.install_path.display()
+            ));
+        }
+        buf.push_str(&format!("Download Size: {}", human_bytes(download_size)));
+        buf.push_str(&format!(
+            "\nNeeded Space: {}{}",
+            if needed_space < 0f64 { "-" } else { "" },
+            human_bytes(needed_space.abs())
+        ));
+        buf.push_str(&format!("\nTotal Disk Size: {}", human_bytes(disk_size)));
+        return Ok((buf, None));
+    }
+
+    if let Err(err) = check_available_space(&install_info.install_path, needed_space) {
+        return Ok((err, None));
+    }
+    if !confirm_download(download_size, needed_space, install_opts.yes) {
+        return Err(CarnivalError::Cancelled);
+    }
+
+    let new_manifest_chunks = match read_cached_build_manifest(
         &version.version,
         slug,
         "manifest_chunks",
+        install_opts.max_manifest_age,
     )
-    .await?;
+    .await
+    {
+        Some(cached) => cached,
+        None => {
+            let new_manifest_chunks =
+                api::product::get_build_manifest_chunks(&client, product, version).await?;
+            store_build_manifest(
+                &new_manifest_chunks,
+                &version.version,
+                slug,
+                "manifest_chunks",
+            )
+            .await?;
+            new_manifest_chunks
+        }
+    };
 
-    let delta_manifest = read_or_generate_delta_manifest(
-        slug,
-        &old_manifest[..],
-        &new_manifest[..],
-        &install_info.version,
-        &version.version,
-    )
-    .await?;
     let delta_manifest_chunks = read_or_generate_delta_chunks_manifest(
         slug,
         &delta_manifest[..],
         &new_manifest_chunks[..],
         &install_info.version,
         &version.version,
+        install_opts.max_manifest_age,
     )
     .await?;
 
-    if install_opts.info {
-        let mut delta_build_manifest_rdr = csv::Reader::from_reader(&delta_manifest[..]);
-        let download_size = delta_build_manifest_rdr
-            .byte_records()
-            .map(|r| {
-                r.expect("Failed to get byte record")
-                    .deserialize::<BuildManifestRecord>(None)
-            })
-            .fold(0f64, |acc, record| match record {
-                Ok(record) => match record.tag {
-                    Some(ChangeTag::Removed) => acc,
-                    _ => acc + record.size_in_bytes as f64,
-                },
-                Err(_) => acc,
-            });
-        let mut new_build_manifest_rdr = csv::Reader::from_reader(&new_manifest[..]);
-        let disk_size = new_build_manifest_rdr
-            .byte_records()
-            .map(|r| {
-                let mut record = r.expect("Failed to get byte record");
-                record.push_field(b"");
-                record.deserialize::<BuildManifestRecord>(None)
-            })
-            .fold(0f64, |acc, record| match record {
-                Ok(record) => acc + record.size_in_bytes as f64,
-                Err(_) => acc,
-            });
-
-        let mut old_manifest_rdr = csv::Reader::from_reader(&old_manifest[..]);
-        let old_disk_size = old_manifest_rdr
-            .byte_records()
-            .map(|r| {
-                let mut record = r.expect("Failed to get byte record");
-                record.push_field(b"");
-                record.deserialize::<BuildManifestRecord>(None)
-            })
-            .fold(0f64, |acc, record| match record {
-                Ok(record) => acc + record.size_in_bytes as f64,
-                Err(_) => acc,
-            });
-
-        let needed_space = disk_size - old_disk_size;
-        println!("{}", needed_space);
+    // Only there if the old build was installed by us, it's used to reuse chunks from it
+    let old_manifest_chunks = read_build_manifest(&install_info.version, slug, "manifest_chunks")
+        .await
+        .ok();
 
-        let mut buf = String::new();
-        buf.push_str(&format!("Download Size: {}", human_bytes(download_size)));
-        buf.push_str(&format!(
-            "\nNeeded Space: {}{}",
-            if needed_space < 0f64 { "-" } else { "" },
-            human_bytes(needed_space.abs())
-        ));
-        buf.push_str(&format!("\nTotal Disk Size: {}", human_bytes(disk_size)));
-        return Ok((buf, None));
+    // Files excluded when installing stay excluded
+    let mut install_opts = install_opts;
+    for pattern in &install_info.excluded {
+        match Pattern::new(pattern) {
+            Ok(pattern) if !install_opts.exclude.contains(&pattern) => {
+                install_opts.exclude.push(pattern)
+            }
+            Ok(_) => {}
+            Err(err) => println!("Ignoring invalid exclude pattern {pattern}: {err}"),
+        }
     }
+    let excluded: Vec<String> = install_opts
+        .exclude
+        .iter()
+        .map(|pattern| pattern.as_str().to_owned())
+        .collect();
 
     let product_arc = Arc::new(product.clone());
     let version_arc = Arc::new(version.os.to_owned());
@@ -300,53 +797,286 @@ pub(crate) async fn update(
         version_arc,
         &delta_manifest[..],
         &delta_manifest_chunks[..],
+        old_manifest_chunks.as_deref(),
         OsPath::from(&install_info.install_path),
         install_opts,
+        progress,
     )
     .await?;
 
-    let install_info = InstallInfo::new(
-        install_info.install_path.to_owned(),
-        version.version.to_owned(),
-        version.os.to_owned(),
-    );
+    let install_info = InstallInfo {
+        version: version.version.to_owned(),
+        os: version.os.to_owned(),
+        excluded,
+        ..install_info.clone()
+    };
     Ok((format!("Updated {slug} successfully."), Some(install_info)))
 }
 
+/// What changed between two builds of a game
+pub(crate) struct BuildDiff {
+    /// The files that were added, modified or removed
+    pub(crate) records: Vec<BuildManifestRecord>,
+    /// How much more disk space the new build takes up. Negative if it's smaller.
+    pub(crate) needed_space: f64,
+    /// How much an update from the old build to the new one downloads. Without the chunks
+    /// manifests of both builds, it's the size of every added and modified file, which is the
+    /// most it can be.
+    pub(crate) download_size: f64,
+}
+
+impl BuildDiff {
+    /// How many files have `tag`. Directories aren't counted.
+    pub(crate) fn count(&self, tag: ChangeTag) -> usize {
+        self.records
+            .iter()
+            .filter(|record| !record.is_directory() && record.tag.as_ref() == Some(&tag))
+            .count()
+    }
+}
+
+/// Compares two builds of `slug`, using their stored manifests
+pub(crate) async fn diff(
+    slug: &String,
+    old_version: &String,
+    new_version: &String,
+    check: bool,
+) -> tokio::io::Result<BuildDiff> {
+    let mut manifests = vec![];
+    for version in [old_version, new_version] {
+        match read_build_manifest(version, slug, "manifest").await {
+            Ok(manifest) => manifests.push(manifest),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!(
+                        "The manifest of build {version} isn't stored. Fetch it with `install {slug} --version {version} --manifest-only`."
+                    ),
+                ));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    // --check promises not to write anything, so the delta isn't stored then
+    let delta_manifest = if check {
+        generate_delta_manifest(&manifests[0][..], &manifests[1][..])
+    } else {
+        read_or_generate_delta_manifest(
+            slug,
+            &manifests[0][..],
+            &manifests[1][..],
+            old_version,
+            new_version,
+            None,
+        )
+        .await?
+    };
+
+    let mut delta_manifest_rdr = csv::Reader::from_reader(&delta_manifest[..]);
+    let records: Vec<BuildManifestRecord> = delta_manifest_rdr
+        .byte_records()
+        .map(|record| record?.deserialize::<BuildManifestRecord>(None))
+        .collect::<Result<_, csv::Error>>()
+        .map_err(std::io::Error::other)?;
+
+    let download_size = match (
+        read_build_manifest(old_version, slug, "manifest_chunks").await,
+        read_build_manifest(new_version, slug, "manifest_chunks").await,
+    ) {
+        (Ok(old_manifest_chunks), Ok(new_manifest_chunks)) => {
+            delta_download_size(&manifests[1], &new_manifest_chunks, &old_manifest_chunks)
+                .map_err(std::io::Error::other)? as f64
+        }
+        _ => records
+            .iter()
+            .filter(|record| record.tag != Some(ChangeTag::Removed))
+            .map(|record| record.size_in_bytes as f64)
+            .sum(),
+    };
+
+    Ok(BuildDiff {
+        records,
+        needed_space: manifest_disk_size(&manifests[1][..]).map_err(std::io::Error::other)?
+            - manifest_disk_size(&manifests[0][..]).map_err(std::io::Error::other)?,
+        download_size,
+    })
+}
+
+/// Tries to identify which build of `product` is installed at `install_path`, so an existing
+/// on-disk install can be managed again without redownloading it.
+///
+/// Builds are checked newest first. A build only matches if every file in its manifest exists
+/// with the right size and passes [`verify`]. With `check`, fetched manifests aren't stored and
+/// the verified files aren't remembered.
+pub(crate) async fn identify_installed_version(
+    client: &reqwest::Client,
+    product: &Product,
+    install_path: &PathBuf,
+    version: Option<&ProductVersion>,
+    check: bool,
+) -> tokio::io::Result<Option<InstallInfo>> {
+    let versions: Vec<&ProductVersion> = match version {
+        Some(version) => vec![version],
+        None => {
+            let mut versions: Vec<&ProductVersion> = product.version.iter().collect();
+            versions.sort_by_key(|v| std::cmp::Reverse(v.date));
+            versions
+        }
+    };
+
+    for version in versions {
+        println!("Checking files against build {}...", version.version);
+        let build_manifest =
+            match read_build_manifest(&version.version, &product.slugged_name, "manifest").await {
+                Ok(manifest) => manifest,
+                Err(_) => match api::product::get_build_manifest(client, product, version).await {
+                    Ok(manifest) if check => manifest.to_vec(),
+                    Ok(manifest) => {
+                        store_build_manifest(
+                            &manifest,
+                            &version.version,
+                            &product.slugged_name,
+                            "manifest",
+                        )
+                        .await?;
+                        manifest.to_vec()
+                    }
+                    Err(err) => {
+                        println!(
+                            "Failed to fetch build manifest for {}: {}",
+                            version.version, err
+                        );
+                        continue;
+                    }
+                },
+            };
+
+        if !file_sizes_match_manifest(&OsPath::from(install_path), &build_manifest[..]).await {
+            continue;
+        }
+
+        let install_info = InstallInfo::new(
+            install_path.to_owned(),
+            version.version.to_owned(),
+            version.os.to_owned(),
+        );
+        if verify_against(
+            &build_manifest,
+            &install_info,
+            *DEFAULT_VERIFY_WORKERS,
+            false,
+            !check,
+            None,
+        )
+        .await?
+        {
+            return Ok(Some(install_info));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Asks before starting a download of [`LARGE_DOWNLOAD_SIZE`] or more, unless `yes` is set.
+/// Smaller downloads, and runs where stdin isn't a terminal, go ahead without asking.
+fn confirm_download(download_size: f64, needed_space: f64, yes: bool) -> bool {
+    if yes || download_size < *LARGE_DOWNLOAD_SIZE || !std::io::stdin().is_terminal() {
+        return true;
+    }
+
+    output::confirm(&format!(
+        "This will download {} and use {}{} of disk space. Continue?",
+        human_bytes(download_size),
+        if needed_space < 0f64 { "-" } else { "" },
+        human_bytes(needed_space.abs())
+    ))
+}
+
 pub(crate) async fn launch(
     client: &reqwest::Client,
     product: &Product,
-    install_info: &InstallInfo,
-    #[cfg(not(target_os = "windows"))] no_wine: bool,
-    #[cfg(not(target_os = "windows"))] wine_bin: Option<PathBuf>,
-    #[cfg(not(target_os = "windows"))] wine_prefix: Option<PathBuf>,
-    wrapper: Option<PathBuf>,
-) -> tokio::io::Result<Option<ExitStatus>> {
+    install_info: &mut InstallInfo,
+    launch_opts: LaunchOpts,
+) -> tokio::io::Result<Option<Launched>> {
+    let LaunchOpts {
+        #[cfg(not(target_os = "windows"))]
+        no_wine,
+        #[cfg(not(target_os = "windows"))]
+            wine: wine_bin,
+        #[cfg(not(target_os = "windows"))]
+        wine_prefix,
+        wrapper,
+        #[cfg(target_os = "linux")]
+        gamemode,
+        #[cfg(target_os = "linux")]
+        mangohud,
+        envs,
+        pre_launch,
+        post_launch,
+        detach,
+        #[cfg(target_os = "windows")]
+        admin,
+        exe: exe_override,
+        refresh,
+        check,
+        offline,
+    } = launch_opts;
     let os = &install_info.os;
 
     #[cfg(not(target_os = "windows"))]
-    let wine_bin = match os {
-        BuildOs::Windows => match wine_bin {
-            Some(wine_bin) => Some(wine_bin),
-            None => {
-                if !no_wine {
-                    println!("You need to set --wine-bin to run Windows games");
-                    return Ok(None);
-                } else {
-                    None
+    let (wine_bin, wine_prefix) = match os {
+        BuildOs::Windows => {
+            // Remember the WINE settings, so they don't have to be passed on every launch
+            if wine_bin.is_some() {
+                install_info.wine_bin = wine_bin;
+            }
+            if wine_prefix.is_some() {
+                install_info.wine_prefix = wine_prefix;
+            }
+
+            match &install_info.wine_bin {
+                Some(wine_bin) => (Some(wine_bin.to_owned()), install_info.wine_prefix.clone()),
+                None => {
+                    // A wrapper (e.g. CrossOver) can run Windows games without being told where
+                    // WINE is
+                    if !no_wine && wrapper.is_none() {
+                        println!("You need to set --wine or --wrapper to run Windows games");
+                        return Ok(None);
+                    } else {
+                        (None, install_info.wine_prefix.clone())
+                    }
                 }
             }
-        },
-        _ => None,
+        }
+        _ => (None, wine_prefix),
     };
 
-    let game_details = match api::product::get_game_details(client, product).await {
-        Ok(details) => details,
-        Err(err) => {
-            println!("Failed to fetch game details. Launch might fail: {:?}", err);
+    // Builds for other OSes can still run through something that knows how, like a VM or a
+    // compatibility layer. WINE is handled above.
+    if *os != BuildOs::Windows && !os.is_native() && wrapper.is_none() {
+        println!(
+            "{} builds can't run on this OS. Use --wrapper to launch them with something that can.",
+            os.name()
+        );
+        return Ok(None);
+    }
 
-            None
-        }
+    let game_details = match &install_info.game_details {
+        Some(details) if !refresh || offline => Some(details.to_owned()),
+        None if offline => None,
+        _ => match api::product::get_game_details(client, product).await {
+            Ok(Some(details)) => {
+                install_info.game_details = Some(details.to_owned());
+                Some(details)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                println!("Failed to fetch game details. Launch might fail: {:?}", err);
+
+                install_info.game_details.to_owned()
+            }
+        },
     };
 
     let exe_path = match game_details {
@@ -368,72 +1098,146 @@ pub(crate) async fn launch(
     };
     let install_path = OsPath::from(&install_info.install_path);
 
-    let exe = match exe_path {
-        Some(path) => install_path.join(path).to_pathbuf(),
-        None => match os {
-            BuildOs::Windows => match find_exe_recursive(&install_info.install_path).await {
-                Some(exe) => exe,
-                None => {
-                    println!("Couldn't find suitable exe...");
-                    return Ok(None);
-                }
-            },
-            #[cfg(target_os = "macos")]
-            BuildOs::Mac => match find_app_recursive(&install_info.install_path).await {
-                Some(app) => {
-                    let plist = find_info_plist(&app);
-                    let mac_executables = MacAppExecutables::with_plist(plist);
-
-                    match mac_executables.executable() {
-                        Some(exe) => exe,
-                        None => {
-                            println!("Couldn't find executable in Info.plist...");
-                            return Ok(None);
+    if let Some(exe_override) = exe_override {
+        // It's remembered, so a typo would break every launch after this one
+        if !install_info.install_path.join(&exe_override).is_file() {
+            println!(
+                "{} doesn't exist in {}",
+                exe_override.display(),
+                install_info.install_path.display()
+            );
+            return Ok(None);
+        }
+        install_info.exe = Some(exe_override);
+    }
+
+    let exe = match (&install_info.exe, exe_path) {
+        (Some(exe), _) => install_info.install_path.join(exe),
+        // The exe path from the game details points at the Windows build, so other builds only
+        // use it if it happens to exist
+        (None, Some(path))
+            if os == &BuildOs::Windows || install_path.join(&path).to_pathbuf().exists() =>
+        {
+            install_path.join(path).to_pathbuf()
+        }
+        (None, _) => {
+            let mut exes = match os {
+                BuildOs::Windows => find_exes_recursive(&install_info.install_path).await,
+                #[cfg(target_os = "macos")]
+                BuildOs::Mac => match find_app_recursive(&install_info.install_path).await {
+                    Some(app) => {
+                        let plist = find_info_plist(&app);
+                        let mac_executables = MacAppExecutables::with_plist(plist);
+
+                        match mac_executables.executable() {
+                            Some(exe) => vec![exe],
+                            None => {
+                                println!("Couldn't find executable in Info.plist...");
+                                return Ok(None);
+                            }
                         }
                     }
+                    None => {
+                        println!("Couldn't find a suitable app...");
+                        return Ok(None);
+                    }
+                },
+                // Executables of builds for other OSes can't be told apart from the other files
+                #[cfg(not(target_os = "macos"))]
+                BuildOs::Mac => {
+                    println!(
+                        "Couldn't find the executable of the macOS build. Use --exe to pick one."
+                    );
+                    return Ok(None);
                 }
-                None => {
-                    println!("Couldn't find a suitable app...");
+                #[cfg(target_os = "linux")]
+                BuildOs::Linux => find_executables_recursive(&install_info.install_path).await,
+                #[cfg(not(target_os = "linux"))]
+                BuildOs::Linux => {
+                    println!(
+                        "Couldn't find the executable of the Linux build. Use --exe to pick one."
+                    );
                     return Ok(None);
                 }
-            },
-            #[cfg(not(target_os = "macos"))]
-            BuildOs::Mac => {
-                println!("You can only launch macOS games on macOS");
+            };
+            if exes.is_empty() {
+                println!("Couldn't find suitable exe...");
                 return Ok(None);
             }
-            BuildOs::Linux => {
-                println!("We don't support launching Linux games yet...");
-                return Ok(None);
+
+            if exes.len() > 1 {
+                let relative_exes: Vec<PathBuf> = exes
+                    .iter()
+                    .map(|exe| {
+                        exe.strip_prefix(&install_info.install_path)
+                            .unwrap_or(exe)
+                            .to_path_buf()
+                    })
+                    .collect();
+                let display_exes: Vec<std::path::Display> =
+                    relative_exes.iter().map(|exe| exe.display()).collect();
+
+                match choose("Found multiple executables:", &display_exes) {
+                    Some(choice) => {
+                        install_info.exe = Some(relative_exes[choice].to_owned());
+                    }
+                    None => {
+                        println!("No executable was picked. Use --exe to choose one.");
+                    }
+                }
             }
-        },
+
+            match &install_info.exe {
+                Some(exe) => install_info.install_path.join(exe),
+                None => exes.swap_remove(0),
+            }
+        }
     };
     println!("{} was selected", exe.display());
 
+    // Linux builds don't always keep the executable bit after being downloaded
+    #[cfg(target_os = "linux")]
+    if os == &BuildOs::Linux && !check {
+        mark_as_executable(&exe).await?;
+    }
+
+    // Without --wine, the wrapper has to start WINE itself
     #[cfg(not(target_os = "windows"))]
-    let should_use_wine = (os == &BuildOs::Windows) && !no_wine;
+    let should_use_wine = (os == &BuildOs::Windows) && !no_wine && wine_bin.is_some();
     #[cfg(target_os = "windows")]
     let should_use_wine = false;
-    #[cfg(target_os = "windows")]
-    let wine_bin: Option<PathBuf> = None;
     let wrapper_string = if wrapper.is_some() {
         wrapper.unwrap_or_default().to_str().unwrap().to_owned()
     } else {
         "".to_owned()
     };
-    let wrapper_vec = if !wrapper_string.is_empty() {
+    #[allow(unused_mut)]
+    let mut wrapper_vec = if !wrapper_string.is_empty() {
         split(&wrapper_string.to_owned()).unwrap()
     } else {
         Vec::<String>::new()
     };
-    let binary = if wrapper_vec.len() > 0 {
-        wrapper_vec[0].to_owned()
-    } else {
-        if should_use_wine {
-            wine_bin.unwrap().to_str().unwrap().to_owned()
-        } else {
-            exe.to_str().unwrap().to_owned()
+    // Same order as `gamemoderun mangohud %command%` in Steam, with --wrapper closest to the game
+    #[cfg(target_os = "linux")]
+    {
+        // --wrapper has to start WINE itself, but these don't know about it
+        if wrapper_vec.is_empty() && should_use_wine && (gamemode || mangohud) {
+            if let Some(wine_bin) = &wine_bin {
+                wrapper_vec.push(wine_bin.to_string_lossy().into_owned());
+            }
+        }
+        if mangohud {
+            wrapper_vec.insert(0, "mangohud".to_owned());
         }
+        if gamemode {
+            wrapper_vec.insert(0, "gamemoderun".to_owned());
+        }
+    }
+    let binary = match wrapper_vec.first() {
+        Some(wrapper_bin) => wrapper_bin.to_owned(),
+        #[cfg(not(target_os = "windows"))]
+        None if should_use_wine => wine_bin.unwrap().to_str().unwrap().to_owned(),
+        None => exe.to_str().unwrap().to_owned(),
     };
 
     let mut command = tokio::process::Command::new(binary);
@@ -443,8 +1247,8 @@ pub(crate) async fn launch(
         }
     };
 
-    if !wrapper_string.is_empty() || should_use_wine {
-        command.arg(exe.to_str().unwrap().to_owned());
+    if !wrapper_vec.is_empty() || should_use_wine {
+        command.arg(exe.to_str().unwrap());
     };
     // TODO:
     // Handle cwd and launch args. Since I don't have games that have these I don't have a
@@ -453,57 +1257,399 @@ pub(crate) async fn launch(
     if let Some(wine_prefix) = wine_prefix {
         command.env("WINEPREFIX", wine_prefix);
     }
-    println!("{} is the CWD", install_path);
-    let mut child = command.current_dir(install_path.to_pathbuf()).spawn()?;
+    // Set last, so they can also override WINEPREFIX
+    command.envs(envs);
+
+    command.current_dir(install_path.to_pathbuf());
+    if check {
+        let command = command.as_std();
+        let argv: Vec<String> = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        println!(
+            "Would run: {}",
+            shlex::try_join(argv.iter().map(|arg| arg.as_str())).unwrap_or(argv.join(" "))
+        );
+        for (key, value) in command.get_envs() {
+            if let Some(value) = value {
+                println!("With {}={}", key.to_string_lossy(), value.to_string_lossy());
+            }
+        }
+        println!("In {}", install_path);
+        #[cfg(target_os = "windows")]
+        if admin {
+            println!("As administrator");
+        }
+        if let Some(pre_launch) = &pre_launch {
+            println!("Would run before launching: {pre_launch}");
+        }
+        if let Some(post_launch) = &post_launch {
+            println!("Would run after exiting: {post_launch}");
+        }
+        if detach {
+            println!("In the background");
+        }
+        return Ok(None);
+    }
+
+    debug!("{} is the CWD", install_path);
+    if let Some(pre_launch) = &pre_launch {
+        let status = run_hook(pre_launch, install_path.to_path()).await?;
+        if !status.success() {
+            println!("The pre-launch command exited with: {status}");
+            return Ok(None);
+        }
+    }
+
+    let started = Local::now();
+    if detach {
+        let pid = spawn_detached(command)?;
+        // The game's exit isn't waited for, so only the launch is recorded
+        install_info.last_played = Some(started);
+        if let Err(err) = write_pid_file(&product.slugged_name, pid).await {
+            println!(
+                "Failed to save the PID of {}: {:?}",
+                product.slugged_name, err
+            );
+        }
+        return Ok(Some(Launched::Detached(pid)));
+    }
 
-    let status = child.wait().await?;
+    #[cfg(target_os = "windows")]
+    let status = match admin {
+        true => windows::elevated_command(command.as_std()).status().await,
+        false => status_with_elevation_fallback(&mut command, &exe).await,
+    };
+    #[cfg(not(target_os = "windows"))]
+    let status = command.status().await;
+    if status.is_ok() {
+        record_play_time(install_info, started);
+    }
+
+    // Cleans up after the pre-launch command, so it also runs if the game failed to start
+    if let Some(post_launch) = &post_launch {
+        match run_hook(post_launch, install_path.to_path()).await {
+            Ok(status) if !status.success() => {
+                println!("The post-launch command exited with: {status}");
+            }
+            Ok(_) => {}
+            Err(err) => println!("Failed to run the post-launch command: {:?}", err),
+        }
+    }
 
-    Ok(Some(status))
+    Ok(Some(Launched::Exited(status?)))
 }
 
-pub(crate) async fn verify(slug: &String, install_info: &InstallInfo) -> tokio::io::Result<bool> {
-    let mut handles: Vec<JoinHandle<bool>> = vec![];
+/// How a launched game ended up
+pub(crate) enum Launched {
+    Exited(ExitStatus),
+    /// Started in the background with `--detach`, with this PID
+    Detached(u32),
+}
 
+/// Starts the game without tying it to this process or its terminal, so it keeps running after
+/// both are gone
+fn spawn_detached(mut command: tokio::process::Command) -> tokio::io::Result<u32> {
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    // A session of its own leaves the terminal behind, so closing it doesn't hang up the game
+    // SAFETY: setsid is async-signal-safe, so it can run between fork and exec
+    #[cfg(unix)]
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    #[cfg(target_os = "windows")]
+    {
+        const DETACHED_PROCESS: u32 = 0x00000008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x00000200;
+        command.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+
+    let child = command.spawn()?;
+    child
+        .id()
+        .ok_or_else(|| std::io::Error::other("The game exited right away"))
+}
+
+/// Saves the PID of a game started with `--detach` to `<slug>.pid`, so scripts can find it. It's
+/// kept in the runtime directory where there is one, since it's stale after a reboot.
+async fn write_pid_file(slug: &str, pid: u32) -> tokio::io::Result<()> {
+    let dir = PROJECT_DIRS
+        .runtime_dir()
+        .unwrap_or_else(|| PROJECT_DIRS.cache_dir());
+    tokio::fs::create_dir_all(dir).await?;
+    tokio::fs::write(dir.join(format!("{slug}.pid")), pid.to_string()).await
+}
+
+/// Runs the game, and runs it again as administrator if Windows says it needs to be
+#[cfg(target_os = "windows")]
+async fn status_with_elevation_fallback(
+    command: &mut tokio::process::Command,
+    exe: &Path,
+) -> tokio::io::Result<ExitStatus> {
+    match command.status().await {
+        Err(err) if windows::needs_elevation(&err) => {
+            println!(
+                "{} needs administrator rights, launching it as administrator...",
+                exe.display()
+            );
+            windows::elevated_command(command.as_std()).status().await
+        }
+        result => result,
+    }
+}
+
+/// Runs a --pre-launch or --post-launch command in `cwd` and waits for it to exit. Commands are
+/// split like a shell would, the same way as the wrapper.
+async fn run_hook(hook: &str, cwd: &Path) -> tokio::io::Result<ExitStatus> {
+    let argv = match split(hook) {
+        Some(argv) if !argv.is_empty() => argv,
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{hook} is not a valid command"),
+            ))
+        }
+    };
+
+    debug!("Running {hook}");
+    tokio::process::Command::new(&argv[0])
+        .args(&argv[1..])
+        .current_dir(cwd)
+        .status()
+        .await
+}
+
+/// Adds the time since `started` to the game's play time
+fn record_play_time(install_info: &mut InstallInfo, started: DateTime<Local>) {
+    let played = (Local::now() - started).to_std().unwrap_or_default();
+    install_info.play_time += played.as_secs();
+    install_info.last_played = Some(started);
+    // Launchers (or WINE) can exit right away while the game keeps running, so the time the game
+    // actually ran for can't be known
+    if played < *MIN_RELIABLE_PLAY_TIME {
+        println!(
+            "The game exited after {}. If it started a launcher, the recorded play time may be inaccurate.",
+            HumanDuration(played)
+        );
+    }
+}
+
+/// Checks every file of an install against the hashes in its build manifest, hashing up to
+/// `workers` files at a time. Shows which files fail, and how many passed at the end.
+///
+/// Files that haven't changed since they last passed are skipped, unless `full` is set. Every
+/// checked file is reported to `progress`.
+pub(crate) async fn verify(
+    slug: &str,
+    install_info: &InstallInfo,
+    workers: usize,
+    full: bool,
+    progress: Option<ProgressSender>,
+) -> tokio::io::Result<bool> {
     let build_manifest = read_build_manifest(&install_info.version, slug, "manifest").await?;
-    let mut build_manifest_rdr = csv::Reader::from_reader(&build_manifest[..]);
-    let build_manifest_byte_records = build_manifest_rdr.byte_records();
+    verify_against(&build_manifest, install_info, workers, full, true, progress).await
+}
 
-    for record in build_manifest_byte_records {
-        let mut record = record.expect("Failed to get byte record");
-        record.push_field(b"");
-        let record = record
-            .deserialize::<BuildManifestRecord>(None)
-            .expect("Failed to deserialize build manifest");
+/// Like [`verify`], but against a build manifest that isn't necessarily stored yet. The files
+/// that passed are only remembered for the next verify if `remember` is set.
+async fn verify_against(
+    build_manifest: &[u8],
+    install_info: &InstallInfo,
+    workers: usize,
+    full: bool,
+    remember: bool,
+    progress: Option<ProgressSender>,
+) -> tokio::io::Result<bool> {
+    let exclude: Vec<Pattern> = install_info
+        .excluded
+        .iter()
+        .filter_map(|pattern| Pattern::new(pattern).ok())
+        .collect();
+    let mut records = vec![];
+    for record in parse_build_manifest(build_manifest).map_err(std::io::Error::other)? {
+        // Excluded files were left out on purpose
+        if !record.is_directory() && !is_excluded(&record.file_name, &exclude) {
+            records.push(record);
+        }
+    }
 
-        if record.is_directory() {
-            continue;
+    let total = records.len();
+    report(progress.as_ref(), ProgressEvent::Started(total as u64)).await;
+    let m = progress_bars();
+    // Grows as files are queued for hashing, so unchanged and missing files don't count
+    let hashed_prog = m.add(ProgressBar::new(0));
+    hashed_prog.set_style(
+        ProgressStyle::with_template(
+            "Hashed {bytes:>7}/{total_bytes:7} at {binary_bytes_per_sec} [{eta_precise}]",
+        )
+        .unwrap(),
+    );
+
+    let mut verify_cache = VerifyCache::load();
+    let mut cached = verify_cache.take(&install_info.install_path);
+    let verified = Arc::new(Mutex::new(HashMap::new()));
+    let mut skipped = 0;
+
+    let semaphore = Arc::new(Semaphore::new(workers.max(1)));
+    let mut handles: Vec<JoinHandle<bool>> = vec![];
+    let mut failed = 0;
+    for record in records {
+        let file_path = manifest_file_path(&install_info.install_path, &record.file_name);
+        let metadata = tokio::fs::symlink_metadata(&file_path).await.ok();
+        if let Some(metadata) = &metadata {
+            if metadata.is_dir() {
+                m.suspend(|| println!("{} is a directory instead of a file", record.file_name));
+                failed += 1;
+                report(
+                    progress.as_ref(),
+                    ProgressEvent::FileVerified(record.file_name, false),
+                )
+                .await;
+                continue;
+            }
+            // Symlinks and other special files (e.g. in macOS app bundles) can't be hashed like
+            // the files they stand in for, so they only have to exist
+            if !metadata.file_type().is_file() {
+                m.suspend(|| {
+                    println!(
+                        "{} isn't a regular file, e.g. a symlink. Its contents aren't checked.",
+                        record.file_name
+                    )
+                });
+                report(
+                    progress.as_ref(),
+                    ProgressEvent::FileVerified(record.file_name, true),
+                )
+                .await;
+                continue;
+            }
+
+            if let Some(verified_file) = cached.remove(&record.file_name) {
+                if !full && verified_file.is_unchanged(metadata, &record.sha) {
+                    debug!("Not hashing {}, it hasn't changed", record.file_name);
+                    verified
+                        .lock()
+                        .unwrap()
+                        .insert(record.file_name.to_owned(), verified_file);
+                    skipped += 1;
+                    report(
+                        progress.as_ref(),
+                        ProgressEvent::FileVerified(record.file_name, true),
+                    )
+                    .await;
+                    continue;
+                }
+            }
         }
 
-        let file_path = OsPath::from(install_info.install_path.join(&record.file_name));
         if !tokio::fs::try_exists(&file_path).await? {
-            println!("{} is missing", record.file_name);
-            return Ok(false);
+            if tokio::fs::try_exists(part_file_path(&file_path)).await? {
+                m.suspend(|| println!("{} didn't finish downloading", record.file_name));
+            } else {
+                m.suspend(|| println!("{} is missing", record.file_name));
+            }
+            failed += 1;
+            report(
+                progress.as_ref(),
+                ProgressEvent::FileVerified(record.file_name, false),
+            )
+            .await;
+            continue;
         }
 
+        hashed_prog.inc_length(record.size_in_bytes as u64);
+        let progress = progress.clone();
+        let hashed_prog = hashed_prog.clone();
+        let semaphore = semaphore.clone();
+        let verified = verified.clone();
         handles.push(tokio::spawn(async move {
-            match verify_file_hash(&file_path, &record.sha) {
-                Ok(result) => result,
+            let _permit = semaphore.acquire().await.unwrap();
+            let sha = record.sha.to_owned();
+            // Hashing is pure CPU and disk work, so keep it off the async runtime
+            let result = match tokio::task::spawn_blocking(move || {
+                verify_file_hash(&file_path, &sha, &hashed_prog)
+            })
+            .await
+            {
+                Ok(Ok(true)) => true,
+                Ok(Ok(false)) => {
+                    m.suspend(|| println!("{} is corrupted", record.file_name));
+                    false
+                }
+                Ok(Err(err)) => {
+                    m.suspend(|| println!("Failed to verify {}: {:?}", record.file_name, err));
+                    false
+                }
                 Err(err) => {
-                    println!("Failed to verify {}: {:?}", record.file_name, err);
-
+                    m.suspend(|| println!("Failed to verify {}: {}", record.file_name, err));
                     false
                 }
+            };
+
+            // The metadata is from before hashing, so a file that changed meanwhile is hashed
+            // again next time
+            if let Some(verified_file) = metadata
+                .filter(|_| result)
+                .and_then(|metadata| VerifiedFile::new(&metadata, record.sha.to_owned()))
+            {
+                verified
+                    .lock()
+                    .unwrap()
+                    .insert(record.file_name.to_owned(), verified_file);
             }
+
+            report(
+                progress.as_ref(),
+                ProgressEvent::FileVerified(record.file_name, result),
+            )
+            .await;
+
+            result
         }));
     }
 
-    let mut result = true;
     for handle in handles {
         if !handle.await? {
-            result = false;
-            break;
+            failed += 1;
         }
     }
 
-    Ok(result)
+    // Files that failed or aren't in the build anymore are left out
+    if remember {
+        let verified = std::mem::take(&mut *verified.lock().unwrap());
+        verify_cache.insert(&install_info.install_path, verified);
+        if let Err(err) = verify_cache.store() {
+            m.suspend(|| println!("Failed to save the verified files: {:?}", err));
+        }
+    }
+
+    let mut summary = format!("{} passed, {} failed", total - failed, failed);
+    if skipped > 0 {
+        summary.push_str(&format!(" ({skipped} unchanged since they last passed)"));
+    }
+    if hashed_prog.position() > 0 {
+        let hashed = hashed_prog.position() as f64;
+        let elapsed = hashed_prog.elapsed().as_secs_f64().max(0.001);
+        summary.push_str(&format!(
+            ", hashed {} at {}/s",
+            human_bytes(hashed),
+            human_bytes(hashed / elapsed)
+        ));
+    }
+    hashed_prog.finish_and_clear();
+    m.suspend(|| match failed {
+        0 => println!("{}", style(summary).green()),
+        _ => println!("{}", style(summary).red()),
+    });
+
+    Ok(failed == 0)
 }
@@ -0,0 +1,81 @@
+//! Remembers which installed files passed verification, so verifying an unchanged game again
+//! doesn't hash every file. A file is hashed again as soon as its size or modification time
+//! changes, or the build expects other contents.
+
+use std::{collections::HashMap, fs::Metadata, path::Path, time::SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants::PROJECT_DIRS;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct VerifiedFile {
+    size: u64,
+    modified: SystemTime,
+    /// The SHA the file was verified against
+    sha: String,
+}
+
+impl VerifiedFile {
+    /// `None` if the filesystem doesn't keep modification times, which the cache relies on
+    pub(crate) fn new(metadata: &Metadata, sha: String) -> Option<VerifiedFile> {
+        Some(VerifiedFile {
+            size: metadata.len(),
+            modified: metadata.modified().ok()?,
+            sha,
+        })
+    }
+
+    /// Whether the file is still the one that passed verification against `sha`
+    pub(crate) fn is_unchanged(&self, metadata: &Metadata, sha: &str) -> bool {
+        self.sha == sha
+            && self.size == metadata.len()
+            && metadata
+                .modified()
+                .is_ok_and(|modified| modified == self.modified)
+    }
+}
+
+/// The verified files of every install, by install path and then by their name in the build
+/// manifest
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct VerifyCache(HashMap<String, HashMap<String, VerifiedFile>>);
+
+fn verify_cache_path() -> std::path::PathBuf {
+    PROJECT_DIRS.cache_dir().join("verified.json")
+}
+
+impl VerifyCache {
+    /// Loads the cache. A missing or unreadable cache is empty, so every file is hashed.
+    pub(crate) fn load() -> VerifyCache {
+        std::fs::read(verify_cache_path())
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    /// Takes the verified files of the install in `install_path` out of the cache
+    pub(crate) fn take(&mut self, install_path: &Path) -> HashMap<String, VerifiedFile> {
+        self.0
+            .remove(&install_path.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+
+    /// Replaces the verified files of the install in `install_path`
+    pub(crate) fn insert(&mut self, install_path: &Path, files: HashMap<String, VerifiedFile>) {
+        self.0
+            .insert(install_path.to_string_lossy().into_owned(), files);
+    }
+
+    /// Saves the cache. Installs that are gone are left out.
+    pub(crate) fn store(mut self) -> std::io::Result<()> {
+        self.0
+            .retain(|install_path, _| Path::new(install_path).is_dir());
+
+        let path = verify_cache_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_vec(&self)?)
+    }
+}